@@ -0,0 +1,80 @@
+use std::fmt;
+
+/* Position of a token in the source, line and column start from 1 */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LogoError {
+    Lex(Span),
+    Parse(String, Span),
+    // where in the source the error happened, if it is known
+    Runtime(String, Option<Span>),
+}
+
+impl LogoError {
+    pub fn parse(msg: impl Into<String>, span: Span) -> Self {
+        LogoError::Parse(msg.into(), span)
+    }
+
+    pub fn runtime(msg: impl Into<String>) -> Self {
+        LogoError::Runtime(msg.into(), None)
+    }
+
+    /* Place a runtime error in the source, unless it already has a more precise place */
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            LogoError::Runtime(msg, None) => LogoError::Runtime(msg, Some(span)),
+            e => e,
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            LogoError::Lex(span) | LogoError::Parse(_, span) => Some(*span),
+            LogoError::Runtime(_, span) => *span,
+        }
+    }
+
+    /* Error message followed by the offending source line and a caret under the token */
+    pub fn render(&self, source: &str) -> String {
+        let mut out = self.to_string();
+        if let Some(span) = self.span() {
+            let line = source.lines().nth(span.line - 1).unwrap_or("");
+            out.push_str(&format!("\n{:>4} | {}\n", span.line, line));
+            out.push_str(&format!(
+                "     | {}{}",
+                " ".repeat(span.column - 1),
+                "^".repeat(span.len.max(1))
+            ));
+        }
+        out
+    }
+}
+
+impl fmt::Display for LogoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogoError::Lex(span) => write!(
+                f,
+                "syntax error at line {}, column {}: unrecognised input",
+                span.line, span.column
+            ),
+            LogoError::Parse(msg, span) => write!(
+                f,
+                "parse error at line {}, column {}: {}",
+                span.line, span.column, msg
+            ),
+            LogoError::Runtime(msg, Some(span)) => write!(
+                f,
+                "runtime error at line {}, column {}: {}",
+                span.line, span.column, msg
+            ),
+            LogoError::Runtime(msg, None) => write!(f, "runtime error: {}", msg),
+        }
+    }
+}
@@ -1,69 +1,565 @@
-use crate::parser::{Command, Expr};
+use crate::color;
+use crate::error::{LogoError, Span};
+use crate::parser::{Block, Command, Expr, MathFn};
+use crate::plotter;
+use crate::raster;
+use crate::scene::{self, FillRule, LineCap, LineJoin, Pen, Primitive, Scene, TurtleShape};
+use crate::svg;
 use rand::Rng;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
+use std::path::Path;
 use std::thread;
 use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub enum Value {
-    _String(String),
+    Word(String),
     Color(String),
     Number(f32),
+    Bool(bool),
+    List(Vec<Value>),
 }
 impl Value {
-    fn get_number(&self) -> f32 {
+    /* Words made of digits, like "42 or word 4 2, are numbers too */
+    fn get_number(&self) -> Result<f32, LogoError> {
+        let number = match self {
+            Value::Number(x) => Some(*x),
+            Value::Word(w) => parse_number(w),
+            _ => None,
+        };
+        number.ok_or_else(|| LogoError::runtime(format!("expected a number, got {}", self)))
+    }
+
+    /* Numbers are words too: first 123 is 1 */
+    fn get_word(&self) -> Result<String, LogoError> {
+        match self {
+            Value::Word(w) => Ok(w.clone()),
+            Value::Number(_) | Value::Bool(_) => Ok(self.to_string()),
+            _ => Err(LogoError::runtime(format!("expected a word, got {}", self))),
+        }
+    }
+
+    /* The words true and false are booleans too */
+    fn get_bool(&self) -> Result<bool, LogoError> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            Value::Word(w) if w.eq_ignore_ascii_case("true") => Ok(true),
+            Value::Word(w) if w.eq_ignore_ascii_case("false") => Ok(false),
+            _ => Err(LogoError::runtime(format!(
+                "expected true or false, got {}",
+                self
+            ))),
+        }
+    }
+
+    fn get_list(&self) -> Result<&Vec<Value>, LogoError> {
+        match self {
+            Value::List(items) => Ok(items),
+            _ => Err(LogoError::runtime(format!("expected a list, got {}", self))),
+        }
+    }
+
+    /* PRINT and TYPE leave out the outer brackets of a list, SHOW keeps them */
+    fn print_form(&self) -> String {
+        match self {
+            Value::List(items) => join_items(items),
+            _ => self.to_string(),
+        }
+    }
+}
+
+fn join_items(items: &[Value]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/* Values are printed the way Logo prints them, 3 rather than 3.0 */
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Value::Number(x) => *x,
-            _ => panic!("get_number: value not a number"),
+            Value::Word(w) => write!(f, "{}", w),
+            Value::Color(c) => write!(f, "{}", c),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::List(items) => write!(f, "[{}]", join_items(items)),
+        }
+    }
+}
+
+fn parse_number(word: &str) -> Option<f32> {
+    let numeric = word
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'));
+    if numeric && word.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-') {
+        word.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn empty_input(op: &str) -> LogoError {
+    LogoError::runtime(format!("{} doesn't like an empty input", op))
+}
+
+/* Elements of a list, or characters of a word */
+fn members(value: &Value) -> Result<Vec<Value>, LogoError> {
+    match value {
+        Value::List(items) => Ok(items.clone()),
+        _ => Ok(value
+            .get_word()?
+            .chars()
+            .map(|c| Value::Word(c.to_string()))
+            .collect()),
+    }
+}
+
+fn first(value: &Value) -> Result<Value, LogoError> {
+    members(value)?
+        .first()
+        .cloned()
+        .ok_or_else(|| empty_input("first"))
+}
+
+fn last(value: &Value) -> Result<Value, LogoError> {
+    members(value)?
+        .last()
+        .cloned()
+        .ok_or_else(|| empty_input("last"))
+}
+
+fn butfirst(value: &Value) -> Result<Value, LogoError> {
+    if let Value::List(items) = value {
+        return match items.split_first() {
+            Some((_, rest)) => Ok(Value::List(rest.to_vec())),
+            None => Err(empty_input("butfirst")),
+        };
+    }
+    let word = value.get_word()?;
+    let mut chars = word.chars();
+    chars.next().ok_or_else(|| empty_input("butfirst"))?;
+    Ok(Value::Word(chars.as_str().to_string()))
+}
+
+fn butlast(value: &Value) -> Result<Value, LogoError> {
+    if let Value::List(items) = value {
+        return match items.split_last() {
+            Some((_, rest)) => Ok(Value::List(rest.to_vec())),
+            None => Err(empty_input("butlast")),
+        };
+    }
+    let word = value.get_word()?;
+    let mut chars = word.chars();
+    chars.next_back().ok_or_else(|| empty_input("butlast"))?;
+    Ok(Value::Word(chars.as_str().to_string()))
+}
+
+fn count(value: &Value) -> Result<Value, LogoError> {
+    Ok(Value::Number(members(value)?.len() as f32))
+}
+
+/* Items are numbered from 1 */
+fn item(index: f32, value: &Value) -> Result<Value, LogoError> {
+    let items = members(value)?;
+    if index >= 1.0 && (index as usize) <= items.len() {
+        Ok(items[index as usize - 1].clone())
+    } else {
+        Err(LogoError::runtime(format!(
+            "item {} is out of range, {} has {} items",
+            index,
+            value,
+            items.len()
+        )))
+    }
+}
+
+/* SENTENCE flattens its inputs by one level, words become one item lists */
+fn sentence(a: Value, b: Value) -> Value {
+    let mut items = vec![];
+    for v in [a, b] {
+        match v {
+            Value::List(vs) => items.extend(vs),
+            v => items.push(v),
+        }
+    }
+    Value::List(items)
+}
+
+fn math(f: MathFn, x: f32) -> Result<f32, LogoError> {
+    let out_of_domain = |name: &str| {
+        Err(LogoError::runtime(format!(
+            "{} doesn't like {} as input",
+            name, x
+        )))
+    };
+    match f {
+        MathFn::Sqrt if x < 0.0 => out_of_domain("sqrt"),
+        MathFn::Sqrt => Ok(x.sqrt()),
+        MathFn::Sin => Ok(x.to_radians().sin()),
+        MathFn::Cos => Ok(x.to_radians().cos()),
+        MathFn::Tan => Ok(x.to_radians().tan()),
+        MathFn::Arctan => Ok(x.atan().to_degrees()),
+        // too big for a number
+        MathFn::Exp if !x.exp().is_finite() => out_of_domain("exp"),
+        MathFn::Exp => Ok(x.exp()),
+        MathFn::Ln if x <= 0.0 => out_of_domain("ln"),
+        MathFn::Log10 if x <= 0.0 => out_of_domain("log10"),
+        MathFn::Ln => Ok(x.ln()),
+        MathFn::Log10 => Ok(x.log10()),
+        MathFn::Abs => Ok(x.abs()),
+        MathFn::Int => Ok(x.trunc()),
+        MathFn::Round => Ok(x.round()),
+        _ => unreachable!("{:?} takes two inputs", f),
+    }
+}
+
+fn math2(f: MathFn, x: f32, y: f32) -> Result<f32, LogoError> {
+    match f {
+        MathFn::Remainder | MathFn::Modulo | MathFn::Quotient if y == 0.0 => {
+            Err(LogoError::runtime("attempt to divide by 0"))
+        }
+        // remainder has the sign of the dividend, modulo the sign of the divisor
+        MathFn::Remainder => Ok(x % y),
+        MathFn::Modulo => Ok(((x % y) + y) % y),
+        MathFn::Quotient => Ok(x / y),
+        // too big, or a root of a negative number
+        MathFn::Power if !x.powf(y).is_finite() => Err(LogoError::runtime(format!(
+            "power doesn't like {} and {} as inputs",
+            x, y
+        ))),
+        MathFn::Power => Ok(x.powf(y)),
+        MathFn::Min => Ok(x.min(y)),
+        MathFn::Max => Ok(x.max(y)),
+        _ => unreachable!("{:?} takes one input", f),
+    }
+}
+
+/* EQUALP: numbers compare by value, words ignore case */
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::List(xs), Value::List(ys)) => {
+            xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| values_equal(x, y))
         }
+        (Value::List(_), _) | (_, Value::List(_)) => false,
+        _ => match (a.get_number(), b.get_number()) {
+            (Ok(x), Ok(y)) => x == y,
+            _ => a.to_string().to_lowercase() == b.to_string().to_lowercase(),
+        },
     }
 }
 
+/* Everything that interrupts the normal flow of a program */
+#[derive(Debug)]
 pub enum LogoErr {
     Stop,
+    Output(Value),
+    Error(LogoError),
+}
+
+impl LogoErr {
+    fn at(self, span: Span) -> Self {
+        match self {
+            LogoErr::Error(e) => LogoErr::Error(e.at(span)),
+            e => e,
+        }
+    }
+}
+
+impl From<LogoError> for LogoErr {
+    fn from(e: LogoError) -> Self {
+        LogoErr::Error(e)
+    }
+}
+
+/* How deep procedures may call each other, so a procedure that never stops is an error rather
+than a stack overflow. The interpreter needs a thread with INTERPRETER_STACK to get this deep */
+pub const MAX_CALL_DEPTH: usize = 5000;
+
+/* Native stack one procedure call takes, with some to spare over the most measured for a call
+inside if and inside a sum in brackets. A debug build keeps every temporary apart, so it needs
+about fifteen times as much */
+const STACK_PER_CALL: usize = if cfg!(debug_assertions) {
+    80 * 1024
+} else {
+    8 * 1024
+};
+
+/* Stack for the thread running the interpreter, room for MAX_CALL_DEPTH calls and the
+commands around them */
+pub const INTERPRETER_STACK: usize = MAX_CALL_DEPTH * STACK_PER_CALL + 1024 * 1024;
+
+/* Variables are stored without the leading ':', and in lowercase as Logo names ignore case */
+fn var_name(name: &str) -> &str {
+    name.trim_start_matches(':')
+}
+
+/* Global variables and one frame of locals per running procedure.
+Logo scoping is dynamic: a procedure sees the locals of every procedure that called it.
+Variables declared with LOCAL have no value until they are assigned with MAKE. */
+#[derive(Debug, Default)]
+pub struct Environment {
+    globals: HashMap<String, Option<Value>>,
+    frames: Vec<HashMap<String, Option<Value>>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment::default()
+    }
+
+    /* Innermost binding of the variable, if it exists anywhere */
+    fn lookup(&mut self, name: &str) -> Option<&mut Option<Value>> {
+        let name = name.to_lowercase();
+        for frame in self.frames.iter_mut().rev() {
+            if let Some(var) = frame.get_mut(&name) {
+                return Some(var);
+            }
+        }
+        self.globals.get_mut(&name)
+    }
+
+    fn get(&mut self, name: &str) -> Result<Value, LogoError> {
+        match self.lookup(name) {
+            Some(Some(value)) => Ok(value.clone()),
+            Some(None) => Err(LogoError::runtime(format!("{} has no value", name))),
+            None => Err(LogoError::runtime(format!(
+                "variable {} was not declared",
+                name
+            ))),
+        }
+    }
+
+    /* Update the innermost binding, a new variable becomes global */
+    fn make(&mut self, name: &str, value: Value) {
+        match self.lookup(name) {
+            Some(var) => *var = Some(value),
+            None => {
+                self.globals.insert(name.to_lowercase(), Some(value));
+            }
+        }
+    }
+
+    fn local(&mut self, name: &str) {
+        match self.frames.last_mut() {
+            Some(frame) => {
+                frame.insert(name.to_lowercase(), None);
+            }
+            // outside of any procedure LOCAL behaves like GLOBAL
+            None => self.global(name),
+        }
+    }
+
+    fn global(&mut self, name: &str) {
+        self.globals.entry(name.to_lowercase()).or_insert(None);
+    }
+}
+
+/* Evaluate top level commands, where there is no procedure for stop or output to end */
+pub fn eval_program(
+    ast: Block,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<(), LogoError> {
+    match eval_all(ast, functions, env, image) {
+        Ok(()) => Ok(()),
+        Err(LogoErr::Stop) => Err(LogoError::runtime(
+            "stop can only be used inside a procedure",
+        )),
+        Err(LogoErr::Output(_)) => Err(LogoError::runtime(
+            "output can only be used inside a procedure",
+        )),
+        Err(LogoErr::Error(e)) => Err(e),
+    }
 }
 
 pub fn eval_all(
-    ast: VecDeque<Command>,
-    functions: &mut HashMap<String, (Vec<String>, VecDeque<Command>)>,
-    variables: &HashMap<String, Value>,
+    ast: Block,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
     image: &mut Image,
 ) -> Result<(), LogoErr> {
-    for cmd in ast {
+    for (cmd, span) in ast {
         // println!(" Parsed to:\n{:?}", cmd);
-        match eval(cmd, functions, variables, image) {
-            Err(e) => {
-                return Err(e);
-            }
-            Ok(()) => {}
-        };
+        eval(cmd, span, functions, env, image).map_err(|e| e.at(span))?;
     }
     Ok(())
 }
 
 fn eval(
     cmd: Command,
-    functions: &mut HashMap<String, (Vec<String>, VecDeque<Command>)>,
-    variables: &HashMap<String, Value>,
+    span: Span,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
     image: &mut Image,
 ) -> Result<(), LogoErr> {
     match cmd {
+        Command::Ask(expr, body) => {
+            let ids = eval_turtle_ids(expr, functions, env, image)?;
+            eval_as(ids, body, functions, env, image)
+        }
+        Command::Each(body) => eval_as(image.active.clone(), body, functions, env, image),
+        Command::Repeat(iters, body) => eval_loop(
+            eval_number(iters, functions, env, image)?,
+            body,
+            functions,
+            env,
+            image,
+        ),
+        Command::If(pred, ifcommands) => eval_ifelse(
+            eval_bool(pred, functions, env, image)?,
+            ifcommands,
+            VecDeque::new(),
+            functions,
+            env,
+            image,
+        ),
+        Command::IfElse(pred, ifcommands, elsecommands) => eval_ifelse(
+            eval_bool(pred, functions, env, image)?,
+            ifcommands,
+            elsecommands,
+            functions,
+            env,
+            image,
+        ),
+        Command::FunctionCall(name, args) => {
+            match call_function(&name, args, functions, env, image)? {
+                Some(value) => Err(LogoError::runtime(format!(
+                    "you don't say what to do with {}",
+                    value
+                ))
+                .into()),
+                None => Ok(()),
+            }
+        }
+        Command::FunctionDeclaration(name, args, cmds) => {
+            functions.insert(name, (args, cmds));
+            Ok(())
+        }
+        Command::Stop => Err(LogoErr::Stop),
+        Command::Make(name, expr) => {
+            let value = eval_expr(expr, functions, env, image)?;
+            env.make(&name, value);
+            Ok(())
+        }
+        Command::Local(name) => {
+            env.local(&name);
+            Ok(())
+        }
+        Command::Global(name) => {
+            env.global(&name);
+            Ok(())
+        }
+        Command::Output(expr) => Err(LogoErr::Output(eval_expr(expr, functions, env, image)?)),
+        Command::Filled(expr, body) => {
+            let color = eval_color(expr, functions, env, image)?;
+            eval_filled(color, body, functions, env, image)
+        }
+        Command::Fill(body) => {
+            let color = image.turtle().fill_color.clone();
+            eval_filled(color, body, functions, env, image)
+        }
+        cmd => eval_command(cmd, span, functions, env, image),
+    }
+}
+
+/* Commands that don't run other commands. Kept apart from eval, so that procedures calling
+themselves need less of the stack for each call */
+fn eval_command(
+    cmd: Command,
+    span: Span,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<(), LogoErr> {
+    // every turtle chosen with tell runs the command on its own
+    if image.active.len() > 1 && is_turtle_command(&cmd) {
+        let ids = image.active.clone();
+        return eval_as(ids, VecDeque::from([(cmd, span)]), functions, env, image);
+    }
+    let turtle_command = is_turtle_command(&cmd);
+    let result = match cmd {
         Command::Forward(expr) => {
-            image.forward(eval_expr(expr, variables).get_number());
+            let x = eval_number(expr, functions, env, image)?;
+            image.forward(x)?;
             Ok(())
         }
         Command::Backward(expr) => {
-            image.backward(eval_expr(expr, variables).get_number());
+            let x = eval_number(expr, functions, env, image)?;
+            image.backward(x)?;
             Ok(())
         }
         Command::Right(expr) => {
-            image.right(eval_expr(expr, variables).get_number());
+            let x = eval_number(expr, functions, env, image)?;
+            image.right(x);
+            Ok(())
+        }
+        Command::SetX(expr) => {
+            let x = eval_number(expr, functions, env, image)?;
+            image.setx(x)?;
+            Ok(())
+        }
+        Command::SetY(expr) => {
+            let y = eval_number(expr, functions, env, image)?;
+            image.sety(y)?;
+            Ok(())
+        }
+        Command::SetXY(x, y) => {
+            let x = eval_number(x, functions, env, image)?;
+            let y = eval_number(y, functions, env, image)?;
+            image.setpos(x, y)?;
+            Ok(())
+        }
+        Command::SetPos(expr) => {
+            let (x, y) = eval_position(expr, functions, env, image)?;
+            image.setpos(x, y)?;
+            Ok(())
+        }
+        Command::SetHeading(expr) => {
+            let heading = eval_number(expr, functions, env, image)?;
+            image.setheading(heading);
+            Ok(())
+        }
+        Command::Home => {
+            image.home()?;
+            Ok(())
+        }
+        Command::Arc(angle, radius) => {
+            let angle = eval_number(angle, functions, env, image)?;
+            let radius = eval_radius(radius, functions, env, image)?;
+            image.arc(angle, radius);
+            Ok(())
+        }
+        Command::Arc2(angle, radius) => {
+            let angle = eval_number(angle, functions, env, image)?;
+            let radius = eval_radius(radius, functions, env, image)?;
+            image.arc2(angle, radius)?;
+            Ok(())
+        }
+        Command::Circle(radius) => {
+            let radius = eval_radius(radius, functions, env, image)?;
+            image.circle(radius);
+            Ok(())
+        }
+        Command::Circle2(radius) => {
+            let radius = eval_radius(radius, functions, env, image)?;
+            image.arc2(360.0, radius)?;
+            Ok(())
+        }
+        Command::Ellipse(crosswise, along) => {
+            let crosswise = eval_radius(crosswise, functions, env, image)?;
+            let along = eval_radius(along, functions, env, image)?;
+            image.ellipse(crosswise, along);
             Ok(())
         }
         Command::Left(expr) => {
-            image.left(eval_expr(expr, variables).get_number());
+            let x = eval_number(expr, functions, env, image)?;
+            image.left(x);
             Ok(())
         }
         Command::PenDown => {
@@ -82,340 +578,2317 @@ fn eval(
             image.hideturtle();
             Ok(())
         }
-        Command::SetTurtle(expr) => {
-            image.setturtle(eval_expr(expr, variables).get_number());
+        Command::SetShape(expr) => {
+            let shape = match eval_word(expr, functions, env, image)?.as_str() {
+                "circle" => TurtleShape::Circle,
+                "triangle" => TurtleShape::Triangle,
+                other => {
+                    return Err(LogoError::runtime(format!(
+                        "setshape: unknown shape {}, use circle or triangle",
+                        other
+                    ))
+                    .into())
+                }
+            };
+            image.setshape(shape);
+            Ok(())
+        }
+        Command::SetPenSize(expr) => {
+            // UCBLogo also accepts [width height], only the width is used
+            let width = match eval_expr(expr, functions, env, image)? {
+                Value::List(items) if !items.is_empty() => items[0].get_number()?,
+                v => v.get_number()?,
+            };
+            if width < 0.0 {
+                return Err(LogoError::runtime(format!(
+                    "setpensize: width can't be negative, got {}",
+                    width
+                ))
+                .into());
+            }
+            image.setpensize(width);
+            Ok(())
+        }
+        Command::SetPenPattern(expr) => {
+            let value = eval_expr(expr, functions, env, image)?;
+            let pattern = value
+                .get_list()?
+                .iter()
+                .map(|v| v.get_number())
+                .collect::<Result<Vec<f32>, LogoError>>()?;
+            if pattern.iter().any(|&d| d < 0.0) {
+                return Err(LogoError::runtime(format!(
+                    "setpenpattern: dash lengths can't be negative, got {}",
+                    value
+                ))
+                .into());
+            }
+            image.setpenpattern(pattern);
+            Ok(())
+        }
+        Command::SetLineCap(expr) => {
+            let cap = match eval_word(expr, functions, env, image)?.as_str() {
+                "butt" => LineCap::Butt,
+                "round" => LineCap::Round,
+                "square" => LineCap::Square,
+                other => {
+                    return Err(LogoError::runtime(format!(
+                        "setlinecap: unknown cap {}, use butt, round or square",
+                        other
+                    ))
+                    .into())
+                }
+            };
+            image.setlinecap(cap);
+            Ok(())
+        }
+        Command::SetLineJoin(expr) => {
+            let join = match eval_word(expr, functions, env, image)?.as_str() {
+                "miter" => LineJoin::Miter,
+                "round" => LineJoin::Round,
+                "bevel" => LineJoin::Bevel,
+                other => {
+                    return Err(LogoError::runtime(format!(
+                        "setlinejoin: unknown join {}, use miter, round or bevel",
+                        other
+                    ))
+                    .into())
+                }
+            };
+            image.setlinejoin(join);
+            Ok(())
+        }
+        Command::SetPenOpacity(expr) => {
+            let opacity = eval_number(expr, functions, env, image)?;
+            if !(0.0..=1.0).contains(&opacity) {
+                return Err(LogoError::runtime(format!(
+                    "setpenopacity: opacity must be between 0 and 1, got {}",
+                    opacity
+                ))
+                .into());
+            }
+            image.setpenopacity(opacity);
+            Ok(())
+        }
+        Command::SetTurtle(expr) | Command::Tell(expr) => {
+            let ids = eval_turtle_ids(expr, functions, env, image)?;
+            image.tell(ids);
             Ok(())
         }
         Command::Wait(expr) => {
-            let x = eval_expr(expr, variables).get_number();
-            thread::sleep(Duration::from_millis((x * 1000.0) as u64));
+            let x = eval_number(expr, functions, env, image)?;
+            image.wait(x);
             Ok(())
         }
         Command::Show(expr) => {
-            println!("{:?}", eval_expr(expr, variables));
+            println!("{}", eval_expr(expr, functions, env, image)?);
             Ok(())
         }
-        Command::Repeat(iters, body) => eval_loop(
-            eval_expr(iters, variables).get_number(),
-            body,
-            functions,
-            variables,
-            image,
-        ),
-        Command::If(pred, ifcommands) => eval_ifelse(
-            eval_expr(pred, variables).get_number(),
-            ifcommands,
-            VecDeque::new(),
-            functions,
-            variables,
-            image,
-        ),
-        Command::IfElse(pred, ifcommands, elsecommands) => eval_ifelse(
-            eval_expr(pred, variables).get_number(),
-            ifcommands,
-            elsecommands,
-            functions,
-            variables,
-            image,
-        ),
-        Command::FunctionCall(name, args) => call_function(name, args, functions, variables, image),
-        Command::FunctionDeclaration(name, args, cmds) => {
-            functions.insert(name, (args, cmds));
+        Command::Print(expr) => {
+            println!("{}", eval_expr(expr, functions, env, image)?.print_form());
+            Ok(())
+        }
+        Command::Type(expr) => {
+            print!("{}", eval_expr(expr, functions, env, image)?.print_form());
+            _ = io::stdout().flush();
             Ok(())
         }
         Command::Clearscreen => {
-            image.clear();
+            image.clearscreen()?;
             Ok(())
         }
-        Command::Stop => Err(LogoErr::Stop),
-        Command::Setcolor(cmd) => {
-            let col = eval_expr(cmd, variables);
-            match col {
-                Value::Color(c) => image.setcolor(c),
-                _ => unimplemented!("setcolor: unimplemented"),
+        Command::Setcolor(expr) => {
+            let color = eval_color(expr, functions, env, image)?;
+            image.setcolor(color);
+            Ok(())
+        }
+        Command::Label(expr) => {
+            let text = eval_expr(expr, functions, env, image)?.print_form();
+            image.label(&text);
+            Ok(())
+        }
+        Command::SetLabelHeight(expr) => {
+            let height = eval_number(expr, functions, env, image)?;
+            if height <= 0.0 {
+                return Err(LogoError::runtime(format!(
+                    "setlabelheight: height must be positive, got {}",
+                    height
+                ))
+                .into());
+            }
+            image.setlabelheight(height);
+            Ok(())
+        }
+        Command::SetLabelFont(expr) => {
+            // a word like "serif or a list like [Times New Roman]
+            let font = eval_expr(expr, functions, env, image)?.print_form();
+            image.setlabelfont(font);
+            Ok(())
+        }
+        Command::SetFillColor(expr) => {
+            let color = eval_color(expr, functions, env, image)?;
+            image.setfillcolor(color);
+            Ok(())
+        }
+        Command::SetFillRule(expr) => {
+            let rule = match eval_word(expr, functions, env, image)?.as_str() {
+                "nonzero" => FillRule::NonZero,
+                "evenodd" => FillRule::EvenOdd,
+                other => {
+                    return Err(LogoError::runtime(format!(
+                        "setfillrule: unknown rule {}, use nonzero or evenodd",
+                        other
+                    ))
+                    .into())
+                }
+            };
+            image.setfillrule(rule);
+            Ok(())
+        }
+        Command::SetCanvas(width, height) => {
+            let width = eval_number(width, functions, env, image)?;
+            let height = eval_number(height, functions, env, image)?;
+            if width <= 0.0 || height <= 0.0 {
+                return Err(LogoError::runtime(format!(
+                    "setcanvas: size must be positive, got {} {}",
+                    width, height
+                ))
+                .into());
+            }
+            image.setcanvas(width, height)?;
+            Ok(())
+        }
+        Command::Window => {
+            image.setmode(BoundaryMode::Window);
+            Ok(())
+        }
+        Command::Wrap => {
+            image.setmode(BoundaryMode::Wrap);
+            Ok(())
+        }
+        Command::Fence => {
+            image.setmode(BoundaryMode::Fence);
+            Ok(())
+        }
+        Command::SetBackground(expr) => {
+            let color = eval_color(expr, functions, env, image)?;
+            image.setbackground(color);
+            Ok(())
+        }
+        Command::SetPalette(index, expr) => {
+            let index = eval_number(index, functions, env, image)?;
+            if index < color::FIXED_PALETTE_COLORS as f32 || index.fract() != 0.0 {
+                return Err(LogoError::runtime(format!(
+                    "setpalette: can't change color {}, use {} or more",
+                    index,
+                    color::FIXED_PALETTE_COLORS
+                ))
+                .into());
             }
+            let color = eval_color(expr, functions, env, image)?;
+            image.palette.insert(index as usize, color);
             Ok(())
-        } // _ => panic!("invalid command"),
+        }
+        _ => unreachable!("eval runs the commands that run other commands"),
+    };
+    if turtle_command {
+        image.tick();
+    }
+    result
+}
+
+fn is_turtle_command(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::Forward(_)
+            | Command::Backward(_)
+            | Command::Right(_)
+            | Command::Left(_)
+            | Command::SetX(_)
+            | Command::SetY(_)
+            | Command::SetXY(_, _)
+            | Command::SetPos(_)
+            | Command::SetHeading(_)
+            | Command::Home
+            | Command::Arc(_, _)
+            | Command::Arc2(_, _)
+            | Command::Circle(_)
+            | Command::Circle2(_)
+            | Command::Ellipse(_, _)
+            | Command::Setcolor(_)
+            | Command::PenUp
+            | Command::PenDown
+            | Command::ShowTurtle
+            | Command::HideTurtle
+            | Command::SetShape(_)
+            | Command::SetPenSize(_)
+            | Command::SetPenPattern(_)
+            | Command::SetLineCap(_)
+            | Command::SetLineJoin(_)
+            | Command::SetPenOpacity(_)
+            | Command::SetFillColor(_)
+            | Command::SetFillRule(_)
+            | Command::Label(_)
+            | Command::SetLabelHeight(_)
+            | Command::SetLabelFont(_)
+    )
+}
+
+/* Run the commands once for every turtle in ids, with only that turtle listening */
+fn eval_as(
+    ids: Vec<usize>,
+    body: Block,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<(), LogoErr> {
+    let (active, who) = (image.active.clone(), image.cur_turtle_id);
+    let mut result = Ok(());
+    for id in ids {
+        image.tell(vec![id]);
+        result = eval_all(body.clone(), functions, env, image);
+        if result.is_err() {
+            break;
+        }
     }
+    image.active = active;
+    image.cur_turtle_id = who;
+    result
+}
+
+/* The shape is filled even if the commands stop with an error half way */
+fn eval_filled(
+    color: String,
+    body: Block,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<(), LogoErr> {
+    image.begin_fill();
+    let result = eval_all(body, functions, env, image);
+    image.end_fill(&color);
+    result
 }
 
 fn eval_ifelse(
-    pred: f32,
-    ifcommands: VecDeque<Command>,
-    elsecommands: VecDeque<Command>,
-    functions: &mut HashMap<String, (Vec<String>, VecDeque<Command>)>,
-    variables: &HashMap<String, Value>,
+    pred: bool,
+    ifcommands: Block,
+    elsecommands: Block,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
     image: &mut Image,
 ) -> Result<(), LogoErr> {
-    let n = pred != 0.0;
-    if n {
-        eval_all(ifcommands, functions, variables, image)
+    if pred {
+        eval_all(ifcommands, functions, env, image)
     } else {
-        eval_all(elsecommands, functions, variables, image)
+        eval_all(elsecommands, functions, env, image)
     }
 }
 
+/* Run a user procedure, returns the value passed to OUTPUT if there was one */
 fn call_function(
-    name: String,
+    name: &str,
     arg_values: Vec<Expr>,
-    functions: &mut HashMap<String, (Vec<String>, VecDeque<Command>)>,
-    variables: &HashMap<String, Value>,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
     image: &mut Image,
-) -> Result<(), LogoErr> {
-    if let Some((arg_names, func_body)) = functions.get(&name) {
-        let func_body = func_body.clone();
+) -> Result<Option<Value>, LogoErr> {
+    if let Some((arg_names, func_body)) = functions.get(name) {
+        let (arg_names, func_body) = (arg_names.clone(), func_body.clone());
         if arg_names.len() != arg_values.len() {
-            panic!("Incorrect number of arguments for function call");
+            return Err(LogoError::runtime(format!(
+                "{} expects {} inputs, got {}",
+                name,
+                arg_names.len(),
+                arg_values.len()
+            ))
+            .into());
         }
-        let local_vars = arg_names
+        if env.frames.len() >= MAX_CALL_DEPTH {
+            return Err(
+                LogoError::runtime(format!("{}: too many nested procedure calls", name)).into(),
+            );
+        }
+        let frame = arg_names
             .iter()
             .zip(arg_values)
-            .map(|(arg, val)| (arg.clone(), eval_expr(val, variables)))
-            .collect::<HashMap<String, Value>>();
+            .map(|(arg, val)| {
+                let value = eval_expr(val, functions, env, image)?;
+                Ok((var_name(arg).to_lowercase(), Some(value)))
+            })
+            .collect::<Result<HashMap<String, Option<Value>>, LogoError>>()?;
 
-        _ = eval_all(func_body, functions, &local_vars, image);
-        Ok(())
+        env.frames.push(frame);
+        let result = eval_all(func_body, functions, env, image);
+        env.frames.pop();
+        /* stop and output end only this procedure, errors go up to the caller */
+        match result {
+            Ok(()) | Err(LogoErr::Stop) => Ok(None),
+            Err(LogoErr::Output(value)) => Ok(Some(value)),
+            Err(e) => Err(e),
+        }
     } else {
-        panic!("Undefined function: {}", name);
+        Err(LogoError::runtime(format!("I don't know how to {}", name)).into())
     }
 }
 
 fn eval_loop(
     iters: f32,
-    commands: VecDeque<Command>,
-    functions: &mut HashMap<String, (Vec<String>, VecDeque<Command>)>,
-    variables: &HashMap<String, Value>,
+    commands: Block,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
     image: &mut Image,
 ) -> Result<(), LogoErr> {
     let n = iters as i32;
-    for _i in 0..(n as i32) {
-        if let Err(e) = eval_all(commands.clone(), functions, variables, image) {
-            return Err(e);
-        };
+    for _i in 0..n {
+        eval_all(commands.clone(), functions, env, image)?;
     }
     Ok(())
 }
 
-fn eval_list(exprs: VecDeque<Expr>, variables: &HashMap<String, Value>) -> Vec<Value> {
+fn eval_list(
+    exprs: Vec<Expr>,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<Vec<Value>, LogoError> {
     let mut result = vec![];
     for e in exprs {
-        result.push(eval_expr(e, variables))
+        result.push(eval_expr(e, functions, env, image)?)
     }
-    result
+    Ok(result)
 }
 
-fn eval_expr(expr: Expr, variables: &HashMap<String, Value>) -> Value {
+fn eval_number(
+    expr: Expr,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<f32, LogoError> {
+    eval_expr(expr, functions, env, image)?.get_number()
+}
+
+fn eval_bool(
+    expr: Expr,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<bool, LogoError> {
+    eval_expr(expr, functions, env, image)?.get_bool()
+}
+
+fn eval_radius(
+    expr: Expr,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<f32, LogoError> {
+    let radius = eval_number(expr, functions, env, image)?;
+    if radius < 0.0 {
+        return Err(LogoError::runtime(format!(
+            "radius can't be negative, got {}",
+            radius
+        )));
+    }
+    Ok(radius)
+}
+
+/* A position is a list of two numbers: [x y] */
+fn eval_position(
+    expr: Expr,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<(f32, f32), LogoError> {
+    let value = eval_expr(expr, functions, env, image)?;
+    match value.get_list()?.as_slice() {
+        [x, y] => Ok((x.get_number()?, y.get_number()?)),
+        _ => Err(LogoError::runtime(format!(
+            "expected a position [x y], got {}",
+            value
+        ))),
+    }
+}
+
+/* A color name or keyword, "#rrggbb, [r g b] from 0 to 255 or a palette index */
+fn eval_color(
+    expr: Expr,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<String, LogoError> {
+    let value = eval_expr(expr, functions, env, image)?;
+    let color = match &value {
+        Value::Color(c) => Some(c.clone()),
+        Value::Number(n) => image.palette_color(*n),
+        Value::Word(w) => color::from_word(w)
+            .or_else(|| value.get_number().ok().and_then(|n| image.palette_color(n))),
+        Value::List(items) => items
+            .iter()
+            .map(|item| item.get_number())
+            .collect::<Result<Vec<f32>, LogoError>>()
+            .ok()
+            .and_then(|rgb| color::from_rgb(&rgb)),
+        Value::Bool(_) => None,
+    };
+    color.ok_or_else(|| LogoError::runtime(format!("{} is not a color", value)))
+}
+
+/* Turtle ids are a number or a list of numbers: 3 or [0 1 2] */
+fn eval_turtle_ids(
+    expr: Expr,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<Vec<usize>, LogoError> {
+    let value = eval_expr(expr, functions, env, image)?;
+    let items = match &value {
+        Value::List(items) => items.clone(),
+        v => vec![v.clone()],
+    };
+    if items.is_empty() {
+        return Err(LogoError::runtime("expected at least one turtle, got []"));
+    }
+    items
+        .iter()
+        .map(|item| match item.get_number()? {
+            n if n >= 0.0 && n.fract() == 0.0 => Ok(n as usize),
+            n => Err(LogoError::runtime(format!(
+                "{} is not a turtle number, use 0, 1, 2, ...",
+                n
+            ))),
+        })
+        .collect()
+}
+
+fn eval_word(
+    expr: Expr,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<String, LogoError> {
+    eval_expr(expr, functions, env, image)?.get_word()
+}
+
+fn eval_expr(
+    expr: Expr,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<Value, LogoError> {
     match expr {
-        Expr::Number(n) => Value::Number(n),
-        Expr::Add(e1, e2) => match (eval_expr(*e1, variables), eval_expr(*e2, variables)) {
-            (Value::Number(n1), Value::Number(n2)) => Value::Number(n1 + n2),
-            // (Value::String(n1), Value::String(n2)) => Value::String(n1+&n2),
-            _ => panic!("add: wrong types"),
-        },
-        Expr::Mul(e1, e2) => match (eval_expr(*e1, variables), eval_expr(*e2, variables)) {
-            (Value::Number(n1), Value::Number(n2)) => Value::Number(n1 * n2),
-            _ => panic!("add: wrong types"),
-        },
-        Expr::Variable(name) => match variables.get(&name) {
-            Some(value) => value.clone(),
-            _ => panic!("variable {} was not declared", name),
-        },
-        Expr::Sub(e1, e2) => match (eval_expr(*e1, variables), eval_expr(*e2, variables)) {
-            (Value::Number(n1), Value::Number(n2)) => Value::Number(n1 - n2),
-            _ => panic!("sub: wrong types"),
-        },
-        Expr::Div(e1, e2) => match (eval_expr(*e1, variables), eval_expr(*e2, variables)) {
-            (Value::Number(n1), Value::Number(n2)) => {
-                if n2 == 0.0 {
-                    panic!("Attempt to divide by 0")
-                } else {
-                    Value::Number(n1 / n2)
-                }
-            }
-            _ => panic!("div: wrong types"),
-        },
-        Expr::Minus(e) => match eval_expr(*e, variables) {
-            Value::Number(n) => Value::Number(-n),
-            _ => panic!("add: wrong types"),
-        },
-        Expr::Lt(e1, e2) => match (eval_expr(*e1, variables), eval_expr(*e2, variables)) {
-            (Value::Number(n1), Value::Number(n2)) => {
-                Value::Number(if n1 < n2 { 1.0 } else { 0.0 })
+        Expr::Number(n) => Ok(Value::Number(n)),
+        Expr::Add(e1, e2) => Ok(Value::Number(
+            eval_number(*e1, functions, env, image)? + eval_number(*e2, functions, env, image)?,
+        )),
+        Expr::Mul(e1, e2) => Ok(Value::Number(
+            eval_number(*e1, functions, env, image)? * eval_number(*e2, functions, env, image)?,
+        )),
+        Expr::Variable(name, span) | Expr::Thing(name, span) => {
+            env.get(var_name(&name)).map_err(|e| e.at(span))
+        }
+        Expr::Sub(e1, e2) => Ok(Value::Number(
+            eval_number(*e1, functions, env, image)? - eval_number(*e2, functions, env, image)?,
+        )),
+        Expr::Div(e1, e2) => {
+            let n1 = eval_number(*e1, functions, env, image)?;
+            let n2 = eval_number(*e2, functions, env, image)?;
+            if n2 == 0.0 {
+                Err(LogoError::runtime("attempt to divide by 0"))
+            } else {
+                Ok(Value::Number(n1 / n2))
             }
-            _ => panic!("(<): wrong types"),
-        },
-        Expr::Eq(e1, e2) => match (eval_expr(*e1, variables), eval_expr(*e2, variables)) {
-            (Value::Number(n1), Value::Number(n2)) => {
-                Value::Number(if n1 == n2 { 1.0 } else { 0.0 })
+        }
+        Expr::Minus(e) => Ok(Value::Number(-eval_number(*e, functions, env, image)?)),
+        Expr::Lt(e1, e2) => Ok(Value::Bool(
+            eval_number(*e1, functions, env, image)? < eval_number(*e2, functions, env, image)?,
+        )),
+        Expr::Gt(e1, e2) => Ok(Value::Bool(
+            eval_number(*e1, functions, env, image)? > eval_number(*e2, functions, env, image)?,
+        )),
+        Expr::Le(e1, e2) => Ok(Value::Bool(
+            eval_number(*e1, functions, env, image)? <= eval_number(*e2, functions, env, image)?,
+        )),
+        Expr::Ge(e1, e2) => Ok(Value::Bool(
+            eval_number(*e1, functions, env, image)? >= eval_number(*e2, functions, env, image)?,
+        )),
+        Expr::Eq(e1, e2) => {
+            let v1 = eval_expr(*e1, functions, env, image)?;
+            let v2 = eval_expr(*e2, functions, env, image)?;
+            Ok(Value::Bool(values_equal(&v1, &v2)))
+        }
+        Expr::Ne(e1, e2) => {
+            let v1 = eval_expr(*e1, functions, env, image)?;
+            let v2 = eval_expr(*e2, functions, env, image)?;
+            Ok(Value::Bool(!values_equal(&v1, &v2)))
+        }
+        Expr::Math(f, e) => math(f, eval_number(*e, functions, env, image)?).map(Value::Number),
+        Expr::Math2(f, e1, e2) => {
+            let x = eval_number(*e1, functions, env, image)?;
+            let y = eval_number(*e2, functions, env, image)?;
+            math2(f, x, y).map(Value::Number)
+        }
+        Expr::Bool(b) => Ok(Value::Bool(b)),
+        // and/or only evaluate the right side when it matters
+        Expr::And(e1, e2) => Ok(Value::Bool(
+            eval_bool(*e1, functions, env, image)? && eval_bool(*e2, functions, env, image)?,
+        )),
+        Expr::Or(e1, e2) => Ok(Value::Bool(
+            eval_bool(*e1, functions, env, image)? || eval_bool(*e2, functions, env, image)?,
+        )),
+        Expr::Not(e) => Ok(Value::Bool(!eval_bool(*e, functions, env, image)?)),
+        Expr::Word(w) => Ok(Value::Word(w)),
+        Expr::FunctionCall(name, args, span) => {
+            match call_function(&name, args, functions, env, image) {
+                Ok(Some(value)) => Ok(value),
+                Ok(None) => {
+                    Err(LogoError::runtime(format!("{} did not output a value", name)).at(span))
+                }
+                Err(LogoErr::Error(e)) => Err(e.at(span)),
+                Err(_) => unreachable!("call_function handles stop and output"),
             }
-            _ => panic!("(=): wrong types"),
+        }
+        expr => eval_reporter(expr, functions, env, image),
+    }
+}
+
+/* Reporters for words, lists and turtles. Kept apart from eval_expr for the same reason as
+eval_command */
+fn eval_reporter(
+    expr: Expr,
+    functions: &mut HashMap<String, (Vec<String>, Block)>,
+    env: &mut Environment,
+    image: &mut Image,
+) -> Result<Value, LogoError> {
+    match expr {
+        Expr::Pos => Ok(Value::List(vec![
+            Value::Number(image.turtle().x),
+            Value::Number(image.turtle().y),
+        ])),
+        Expr::XCor => Ok(Value::Number(image.turtle().x)),
+        Expr::YCor => Ok(Value::Number(image.turtle().y)),
+        Expr::Heading => Ok(Value::Number(image.turtle().heading)),
+        Expr::Towards(e) => {
+            let (x, y) = eval_position(*e, functions, env, image)?;
+            Ok(Value::Number(image.towards(x, y)))
+        }
+        Expr::Distance(e) => {
+            let (x, y) = eval_position(*e, functions, env, image)?;
+            Ok(Value::Number(image.distance(x, y)))
+        }
+        Expr::PenDownP => Ok(Value::Bool(image.turtle().pen_down)),
+        Expr::ShownP => Ok(Value::Bool(image.turtle().visible)),
+        Expr::PenColor => Ok(Value::Color(image.turtle().pen_color.clone())),
+        Expr::Who => match image.active.as_slice() {
+            [id] => Ok(Value::Number(*id as f32)),
+            ids => Ok(Value::List(
+                ids.iter().map(|&id| Value::Number(id as f32)).collect(),
+            )),
         },
-        Expr::Rand(e) => match eval_expr(*e, variables) {
-            Value::Number(n) => {
+        Expr::Turtles => Ok(Value::List(
+            image
+                .turtle_ids()
+                .into_iter()
+                .map(|id| Value::Number(id as f32))
+                .collect(),
+        )),
+        Expr::Concat(e1, e2) => {
+            let w1 = eval_word(*e1, functions, env, image)?;
+            let w2 = eval_word(*e2, functions, env, image)?;
+            Ok(Value::Word(w1 + &w2))
+        }
+        Expr::First(e) => first(&eval_expr(*e, functions, env, image)?),
+        Expr::Last(e) => last(&eval_expr(*e, functions, env, image)?),
+        Expr::ButFirst(e) => butfirst(&eval_expr(*e, functions, env, image)?),
+        Expr::ButLast(e) => butlast(&eval_expr(*e, functions, env, image)?),
+        Expr::Count(e) => count(&eval_expr(*e, functions, env, image)?),
+        Expr::Rand(e) => match eval_number(*e, functions, env, image)? {
+            n if n >= 1.0 => {
                 let mut rng = rand::thread_rng();
-                Value::Number(rng.gen_range(0..(n as i32)) as f32)
+                Ok(Value::Number(rng.gen_range(0..(n as i32)) as f32))
             }
-            _ => panic!("rand: wrong types"),
+            n => Err(LogoError::runtime(format!(
+                "random: input must be at least 1, got {}",
+                n
+            ))),
         },
-        Expr::Color(c) => Value::Color(c),
-        Expr::Pick(exprs) => {
-            let lst = eval_list(exprs, variables);
-            let mut rng = rand::thread_rng();
-            if let Some(v) = lst.get(rng.gen_range(0..lst.len())) {
-                v.clone()
-            } else {
-                panic!("pick needs at least one option, bu vector is empty")
+        Expr::Color(c) => Ok(Value::Color(c)),
+        Expr::Pick(e) => {
+            let lst = members(&eval_expr(*e, functions, env, image)?)?;
+            if lst.is_empty() {
+                return Err(LogoError::runtime(
+                    "pick needs at least one option, but the list is empty",
+                ));
             }
+            let mut rng = rand::thread_rng();
+            Ok(lst[rng.gen_range(0..lst.len())].clone())
+        }
+        Expr::List(items) => Ok(Value::List(eval_list(items, functions, env, image)?)),
+        Expr::ListOf(e1, e2) => Ok(Value::List(vec![
+            eval_expr(*e1, functions, env, image)?,
+            eval_expr(*e2, functions, env, image)?,
+        ])),
+        Expr::Sentence(e1, e2) => {
+            let v1 = eval_expr(*e1, functions, env, image)?;
+            let v2 = eval_expr(*e2, functions, env, image)?;
+            Ok(sentence(v1, v2))
         }
+        Expr::Fput(e1, e2) => {
+            let v = eval_expr(*e1, functions, env, image)?;
+            let mut items = eval_expr(*e2, functions, env, image)?.get_list()?.clone();
+            items.insert(0, v);
+            Ok(Value::List(items))
+        }
+        Expr::Lput(e1, e2) => {
+            let v = eval_expr(*e1, functions, env, image)?;
+            let mut items = eval_expr(*e2, functions, env, image)?.get_list()?.clone();
+            items.push(v);
+            Ok(Value::List(items))
+        }
+        Expr::Item(e1, e2) => {
+            let index = eval_number(*e1, functions, env, image)?;
+            item(index, &eval_expr(*e2, functions, env, image)?)
+        }
+        Expr::EmptyP(e) => {
+            let v = eval_expr(*e, functions, env, image)?;
+            Ok(Value::Bool(members(&v)?.is_empty()))
+        }
+        Expr::MemberP(e1, e2) => {
+            let v = eval_expr(*e1, functions, env, image)?;
+            let items = members(&eval_expr(*e2, functions, env, image)?)?;
+            Ok(Value::Bool(items.iter().any(|item| values_equal(&v, item))))
+        }
+        _ => unreachable!("eval_expr evaluates arithmetic, logic and procedure calls"),
     }
 }
 
-pub struct Image {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoundaryMode {
+    // the canvas is endless, lines outside it are not drawn
+    Window,
+    // leaving one edge comes back at the opposite one
+    Wrap,
+    // leaving the canvas is an error
+    Fence,
+}
+
+/* Turtle coordinates are Logo coordinates: (0, 0) is the centre of the canvas, y points up
+and heading 0 is north, growing clockwise. They are converted to SVG pixels when saving. */
+#[derive(Debug, Clone)]
+pub struct Turtle {
     x: f32,
     y: f32,
-    angle: f32,
-    svg: String,
+    heading: f32,
+    pen_down: bool,
+    pen_color: String,
+    pen_width: f32,
+    // lengths of dashes and gaps, empty for a solid line
+    pen_pattern: Vec<f32>,
+    line_cap: LineCap,
+    line_join: LineJoin,
+    pen_opacity: f32,
+    fill_color: String,
+    fill_rule: FillRule,
+    label_height: f32,
+    label_font: String,
+    shape: TurtleShape,
+    visible: bool,
+}
+
+impl Turtle {
+    /* New turtles start at the centre, facing up, visible, with the pen down */
+    fn new() -> Self {
+        Turtle {
+            x: 0.0,
+            y: 0.0,
+            heading: 0.0,
+            pen_down: true,
+            pen_color: "black".to_string(),
+            pen_width: 1.0,
+            pen_pattern: vec![],
+            line_cap: LineCap::Butt,
+            line_join: LineJoin::Miter,
+            pen_opacity: 1.0,
+            fill_color: "green".to_string(),
+            fill_rule: FillRule::NonZero,
+            label_height: 12.0,
+            label_font: "sans-serif".to_string(),
+            shape: TurtleShape::Circle,
+            visible: true,
+        }
+    }
+
+    fn pen(&self) -> Pen {
+        Pen {
+            color: self.pen_color.clone(),
+            width: self.pen_width,
+            pattern: self.pen_pattern.clone(),
+            cap: self.line_cap,
+            join: self.line_join,
+            opacity: self.pen_opacity,
+        }
+    }
+}
+
+/* Space left around the drawing when the image is fitted to it */
+const AUTOFIT_MARGIN: f32 = 10.0;
+
+/* Snapshots of the image taken while the program runs, for animations. The drawing is kept once
+and a frame only knows which of it was drawn by then, so long animations don't fill the memory */
+struct Frames {
+    // a frame is taken after this many turtle commands
+    every: usize,
+    delay_ms: u32,
+    since_last: usize,
+    // the first frame showing each primitive of the scene, kept in step with it
+    shown_in: Vec<usize>,
+    // drawings wiped by clearscreen, with the first frame showing each of their primitives
+    cleared: Vec<(Scene, Vec<usize>)>,
+    recorded: Vec<Frame>,
+}
+
+struct Frame {
+    // the drawing shown, an index in cleared or the one still on screen after them
+    drawing: usize,
+    turtles: Vec<Primitive>,
+    delay_ms: u32,
+}
+
+/* In wrap mode a move crosses the edges at most this many times, then the turtle is put straight
+where it ends up, so a huge move can't draw forever */
+const MAX_WRAPS: usize = 1000;
+
+/* How long the finished drawing stays on screen before an animation starts again */
+const FINAL_FRAME_MS: u32 = 3000;
+
+/* Turtle path recorded while running a filled block */
+struct FillPath {
+    // where in the scene the shape goes, so it is drawn below its outline
+    index: usize,
+    // every pen up move starts a new part of the path
+    parts: Vec<Vec<(f32, f32)>>,
+}
+
+pub struct Image {
+    scene: Scene,
     width: f32,
     height: f32,
-    pen_width: f32,
-    pen_color: String,
-    pen_active: bool,
-    turtle_visible: bool,
     turtle_size: f32,
-    turtle_color: String,
+    mode: BoundaryMode,
+    frames: Option<Frames>,
+    svg_animation: Option<svg::Animation>,
+    // fit the saved image to the drawing instead of the canvas
+    autofit: bool,
+    // box around everything drawn, in Logo coordinates: min x, min y, max x, max y
+    bounds: Option<(f32, f32, f32, f32)>,
+    // paths traced inside filled blocks, innermost last
+    fills: Vec<FillPath>,
+    // the next line can go on from the last polyline, until something else comes between
+    joinable: bool,
+    svg_options: svg::Options,
+    palette: BTreeMap<usize, String>,
     cur_turtle_id: usize,
-    turtles: Vec<(f32, f32, bool)>,
+    // turtles that obey turtle commands, chosen with tell
+    active: Vec<usize>,
+    turtles: BTreeMap<usize, Turtle>,
 }
 impl Image {
     pub fn new(w: f32, h: f32) -> Self {
         Image {
-            x: w / 2.0,
-            y: h / 2.0,
-            angle: -90.0,
             width: w,
             height: h,
-            pen_color: "black".to_string(),
-            pen_width: 1.0,
-            pen_active: true,
-            turtle_visible: true,
             turtle_size: 10.0,
-            turtle_color: "green".to_string(),
-            turtles: vec![(w / 2.0, h / 2.0, true)],
+            mode: BoundaryMode::Window,
+            frames: None,
+            svg_animation: None,
+            autofit: false,
+            bounds: None,
+            fills: vec![],
+            joinable: false,
+            svg_options: svg::Options::default(),
+            palette: color::DEFAULT_PALETTE
+                .iter()
+                .enumerate()
+                .map(|(i, &[r, g, b])| (i, color::rgb(r, g, b)))
+                .collect(),
+            turtles: BTreeMap::from([(0, Turtle::new())]),
             cur_turtle_id: 0,
-            svg: format!("<svg width=\"{}\" height=\"{}\">", w, h).to_string(),
+            active: vec![0],
+            scene: Scene::default(),
         }
     }
 
+    /* The turtle that receives commands */
+    fn turtle(&self) -> &Turtle {
+        &self.turtles[&self.cur_turtle_id]
+    }
+
+    fn turtle_mut(&mut self) -> &mut Turtle {
+        self.turtles
+            .get_mut(&self.cur_turtle_id)
+            .expect("current turtle exists")
+    }
+
+    pub fn set_autofit(&mut self, autofit: bool) {
+        self.autofit = autofit;
+    }
+
     fn clear(&mut self) {
-        self.svg = format!("<svg width=\"{}\" height=\"{}\">", self.width, self.height).to_string()
+        if let Some(frames) = &mut self.frames {
+            let shown_in = std::mem::take(&mut frames.shown_in);
+            frames.cleared.push((self.scene.clone(), shown_in));
+        }
+        self.scene.clear();
+        if let Some(animation) = &mut self.svg_animation {
+            animation.clear();
+        }
+        self.joinable = false;
+        self.bounds = None;
+    }
+
+    /* The shapes being filled have their place in the drawing, which clearing would take away */
+    fn clearscreen(&mut self) -> Result<(), LogoError> {
+        if !self.fills.is_empty() {
+            return Err(LogoError::runtime(
+                "clearscreen can't be used inside filled",
+            ));
+        }
+        self.clear();
+        Ok(())
+    }
+
+    /* Resize the canvas, keeping what is drawn at the same place relative to the centre */
+    fn setcanvas(&mut self, width: f32, height: f32) -> Result<(), LogoError> {
+        if !self.fills.is_empty() {
+            return Err(LogoError::runtime("setcanvas can't be used inside filled"));
+        }
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    /* Grow the bounding box by a box given in Logo coordinates */
+    fn extend_bounds(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) {
+        let (x1, x2) = (x1.min(x2), x1.max(x2));
+        let (y1, y2) = (y1.min(y2), y1.max(y2));
+        self.bounds = Some(match self.bounds {
+            Some((a, b, c, d)) => (a.min(x1), b.min(y1), c.max(x2), d.max(y2)),
+            None => (x1, y1, x2, y2),
+        });
     }
 
     fn setcolor(&mut self, color: String) {
-        self.pen_color = color;
+        self.turtle_mut().pen_color = color;
+    }
+
+    fn setbackground(&mut self, color: String) {
+        self.scene.set_background(color);
+    }
+
+    fn setlabelheight(&mut self, height: f32) {
+        self.turtle_mut().label_height = height;
+    }
+
+    fn setlabelfont(&mut self, font: String) {
+        self.turtle_mut().label_font = font;
+    }
+
+    /* Text starting at the turtle, turned with it: heading 0 gives normal horizontal text */
+    fn label(&mut self, text: &str) {
+        let t = self.turtle();
+        let label = Primitive::Label {
+            position: (t.x, t.y),
+            heading: t.heading,
+            text: text.to_string(),
+            font: t.label_font.clone(),
+            height: t.label_height,
+            color: t.pen_color.clone(),
+            opacity: t.pen_opacity,
+        };
+        // rough size of the text, whichever way it is turned
+        let size = t
+            .label_height
+            .max(0.6 * t.label_height * text.chars().count() as f32);
+        let (x, y) = (t.x, t.y);
+        self.draw(label);
+        self.extend_bounds(x - size, y - size, x + size, y + size);
+    }
+
+    fn setfillcolor(&mut self, color: String) {
+        self.turtle_mut().fill_color = color;
+    }
+
+    fn setfillrule(&mut self, rule: FillRule) {
+        self.turtle_mut().fill_rule = rule;
+    }
+
+    /* Start recording the path of the turtle for a filled shape */
+    fn begin_fill(&mut self) {
+        self.joinable = false;
+        let start = (self.turtle().x, self.turtle().y);
+        self.fills.push(FillPath {
+            index: self.scene.len(),
+            parts: vec![vec![start]],
+        });
+    }
+
+    /* Fill the path recorded since the matching begin_fill as one closed shape */
+    fn end_fill(&mut self, color: &str) {
+        self.joinable = false;
+        let fill = self.fills.pop().expect("begin_fill was called");
+        let parts: Vec<Vec<(f32, f32)>> = fill
+            .parts
+            .into_iter()
+            .filter(|part| part.len() > 1)
+            .collect();
+        if parts.is_empty() {
+            return;
+        }
+        for &(x, y) in parts.iter().flatten() {
+            self.extend_bounds(x, y, x, y);
+        }
+        let shape = Primitive::Fill {
+            parts,
+            color: color.to_string(),
+            rule: self.turtle().fill_rule,
+        };
+        self.draw_at(fill.index, shape);
+    }
+
+    fn palette_color(&self, index: f32) -> Option<String> {
+        if index < 0.0 || index.fract() != 0.0 {
+            return None;
+        }
+        self.palette.get(&(index as usize)).cloned()
     }
 
     fn penup(&mut self) {
-        self.pen_active = false;
+        self.turtle_mut().pen_down = false;
     }
     fn pendown(&mut self) {
-        self.pen_active = true;
+        self.turtle_mut().pen_down = true;
     }
 
     fn showturtle(&mut self) {
-        self.turtle_visible = true;
+        self.turtle_mut().visible = true;
     }
     fn hideturtle(&mut self) {
-        self.turtle_visible = false;
+        self.turtle_mut().visible = false;
     }
 
-    fn saveturtle(&mut self) {
-        self.turtles[self.cur_turtle_id] = (self.x, self.y, self.turtle_visible);
+    fn setshape(&mut self, shape: TurtleShape) {
+        self.turtle_mut().shape = shape;
     }
 
-    fn loadturtle(&mut self, t: (f32, f32, bool), n:usize) {
-        let (x, y, v) = t;
-        self.x = x;
-        self.y = y;
-        self.turtle_visible = v;
-        self.cur_turtle_id=n;
+    fn setpensize(&mut self, width: f32) {
+        self.turtle_mut().pen_width = width;
     }
 
-    fn newturtle(&mut self) {
-        self.x = self.width / 2.0;
-        self.y = self.height / 2.0;
-        self.turtle_visible = true;
-        self.cur_turtle_id = self.turtles.len();
-        self.turtles.push((self.x, self.y, true));
+    fn setpenpattern(&mut self, pattern: Vec<f32>) {
+        self.turtle_mut().pen_pattern = pattern;
     }
 
-    fn setturtle(&mut self, x: f32) {
-        let n = x as usize;
-        self.saveturtle();
-        if let Some(t) = self.turtles.get(n) {
-            self.loadturtle(*t, n);
-        } else if n == self.turtles.len() {
-            self.newturtle()
-        } else {
-            panic!("can't add turtle {}", n);
+    fn setlinecap(&mut self, cap: LineCap) {
+        self.turtle_mut().line_cap = cap;
+    }
+
+    fn setlinejoin(&mut self, join: LineJoin) {
+        self.turtle_mut().line_join = join;
+    }
+
+    fn setpenopacity(&mut self, opacity: f32) {
+        self.turtle_mut().pen_opacity = opacity;
+    }
+
+    /* Make the turtles listen to commands, turtles used for the first time are created */
+    fn tell(&mut self, ids: Vec<usize>) {
+        for &id in &ids {
+            self.turtles.entry(id).or_insert_with(Turtle::new);
         }
+        self.cur_turtle_id = ids[0];
+        self.active = ids;
+    }
+
+    fn turtle_ids(&self) -> Vec<usize> {
+        self.turtles.keys().copied().collect()
     }
 
     fn calculate_new_position(&self, dist: f32) -> (f32, f32) {
-        let angle_rad = self.angle.to_radians();
-        let new_x = self.x + dist * angle_rad.cos();
-        let new_y = self.y + dist * angle_rad.sin();
+        let t = self.turtle();
+        let heading_rad = t.heading.to_radians();
+        let new_x = t.x + dist * heading_rad.sin();
+        let new_y = t.y + dist * heading_rad.cos();
         (new_x, new_y)
     }
-    fn add_line_to_svg(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) {
-        let line = format!(
-            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"{}\" />\n",
-            x1, y1, x2, y2, self.pen_color, self.pen_width
-        );
-        self.svg.push_str(&line);
+
+    fn inside(&self, x: f32, y: f32) -> bool {
+        x.abs() <= self.width / 2.0 && y.abs() <= self.height / 2.0
     }
-    fn forward(&mut self, dist: f32) {
-        let (new_x, new_y) = self.calculate_new_position(dist);
-        if self.pen_active {
-            self.add_line_to_svg(self.x, self.y, new_x, new_y);
+
+    /* Part of the line inside the canvas (Liang-Barsky), None if it is all outside */
+    fn clip_line(&self, x1: f32, y1: f32, x2: f32, y2: f32) -> Option<(f32, f32, f32, f32)> {
+        let (w, h) = (self.width / 2.0, self.height / 2.0);
+        scene::clip_line((x1, y1), (x2, y2), (-w, -h, w, h))
+            .map(|((x1, y1), (x2, y2))| (x1, y1, x2, y2))
+    }
+
+    /* Line in Logo coordinates, going on from the last one when it starts where that one ended */
+    fn add_line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) {
+        // when fitting the image to the drawing nothing is outside of it
+        let clipped = match self.autofit {
+            true => Some((x1, y1, x2, y2)),
+            false => self.clip_line(x1, y1, x2, y2),
+        };
+        let Some((x1, y1, x2, y2)) = clipped else {
+            return;
+        };
+        self.extend_bounds(x1, y1, x2, y2);
+        let pen = self.turtle().pen();
+        if let (true, Some(Primitive::Polyline { points, pen: last })) =
+            (self.joinable, self.scene.last_mut())
+        {
+            if *last == pen && points.last() == Some(&(x1, y1)) {
+                points.push((x2, y2));
+                if let Some(animation) = &mut self.svg_animation {
+                    animation.extend_last();
+                }
+                return;
+            }
+        }
+        let line = Primitive::Polyline {
+            points: vec![(x1, y1), (x2, y2)],
+            pen,
+        };
+        self.draw(line);
+        self.joinable = true;
+    }
+
+    /* Put a primitive on top of the drawing */
+    fn draw(&mut self, primitive: Primitive) {
+        self.draw_at(self.scene.len(), primitive);
+    }
+
+    /* Put a primitive at a place in the drawing, in an animation it is drawn now */
+    fn draw_at(&mut self, index: usize, primitive: Primitive) {
+        self.scene.insert(index, primitive);
+        if let Some(animation) = &mut self.svg_animation {
+            animation.insert(index);
+        }
+        if let Some(frames) = &mut self.frames {
+            frames.shown_in.insert(index, frames.recorded.len());
+        }
+    }
+
+    /* Move the turtle to an absolute position, drawing a line if the pen is down */
+    fn setpos(&mut self, new_x: f32, new_y: f32) -> Result<(), LogoError> {
+        if !new_x.is_finite() || !new_y.is_finite() {
+            return Err(LogoError::runtime(format!(
+                "can't move the turtle to [{} {}]",
+                new_x, new_y
+            )));
+        }
+        match self.mode {
+            BoundaryMode::Window => self.move_to(new_x, new_y),
+            BoundaryMode::Fence => {
+                if !self.inside(new_x, new_y) {
+                    return Err(LogoError::runtime(format!(
+                        "turtle out of bounds at [{} {}]",
+                        new_x, new_y
+                    )));
+                }
+                self.move_to(new_x, new_y)
+            }
+            BoundaryMode::Wrap => self.wrap_to(new_x, new_y),
+        }
+        Ok(())
+    }
+
+    fn move_to(&mut self, new_x: f32, new_y: f32) {
+        let (x, y, pen_down) = (self.turtle().x, self.turtle().y, self.turtle().pen_down);
+        if pen_down {
+            self.add_line(x, y, new_x, new_y);
+        }
+        self.add_fill_point(new_x, new_y, pen_down);
+        let t = self.turtle_mut();
+        t.x = new_x;
+        t.y = new_y;
+    }
+
+    /* Walk towards the point, jumping to the opposite edge whenever the turtle leaves the canvas */
+    fn wrap_to(&mut self, new_x: f32, new_y: f32) {
+        let (w, h) = (self.width / 2.0, self.height / 2.0);
+        let (mut dx, mut dy) = (new_x - self.turtle().x, new_y - self.turtle().y);
+        for _ in 0..MAX_WRAPS {
+            let (x, y) = (self.turtle().x, self.turtle().y);
+            // fraction of the remaining move left before crossing an edge
+            let (mut t, mut jump) = (1.0, None);
+            if dx != 0.0 {
+                let edge = if dx > 0.0 { w } else { -w };
+                let tx = (edge - x) / dx;
+                if tx < t {
+                    (t, jump) = (tx.max(0.0), Some((-edge, y + tx.max(0.0) * dy)));
+                }
+            }
+            if dy != 0.0 {
+                let edge = if dy > 0.0 { h } else { -h };
+                let ty = (edge - y) / dy;
+                if ty < t {
+                    (t, jump) = (ty.max(0.0), Some((x + ty.max(0.0) * dx, -edge)));
+                }
+            }
+            if t > 0.0 {
+                self.move_to(x + t * dx, y + t * dy);
+            }
+            let Some((jump_x, jump_y)) = jump else {
+                return;
+            };
+            (dx, dy) = (dx * (1.0 - t), dy * (1.0 - t));
+            self.add_fill_point(jump_x, jump_y, false);
+            let turtle = self.turtle_mut();
+            (turtle.x, turtle.y) = (jump_x, jump_y);
+        }
+        // the rest of a very long move is not drawn
+        let (x, y) = (self.turtle().x, self.turtle().y);
+        let (end_x, end_y) = self.wrap_position(x + dx, y + dy);
+        self.add_fill_point(end_x, end_y, false);
+        let turtle = self.turtle_mut();
+        (turtle.x, turtle.y) = (end_x, end_y);
+    }
+
+    /* Put a turtle that is off the canvas back on it, as if it had wrapped around */
+    fn wrap_position(&self, x: f32, y: f32) -> (f32, f32) {
+        let (w, h) = (self.width, self.height);
+        (
+            (x + w / 2.0).rem_euclid(w) - w / 2.0,
+            (y + h / 2.0).rem_euclid(h) - h / 2.0,
+        )
+    }
+
+    fn setmode(&mut self, mode: BoundaryMode) {
+        if mode == BoundaryMode::Wrap {
+            let positions: Vec<(usize, (f32, f32))> = self
+                .turtles
+                .iter()
+                .map(|(&id, t)| (id, self.wrap_position(t.x, t.y)))
+                .collect();
+            for (id, (x, y)) in positions {
+                let t = self.turtles.get_mut(&id).expect("turtle exists");
+                (t.x, t.y) = (x, y);
+            }
         }
-        self.x = new_x;
-        self.y = new_y;
-        // println!("image-forward {}, {}", self.x, self.y);
+        self.mode = mode;
     }
 
-    fn backward(&mut self, dist: f32) {
-        self.forward(-dist);
-        // println!("image-backward {}, {}", self.x, self.y);
+    fn add_fill_point(&mut self, x: f32, y: f32, pen_down: bool) {
+        for fill in &mut self.fills {
+            match fill.parts.last_mut() {
+                Some(part) if pen_down || part.len() == 1 => {
+                    if !pen_down {
+                        part.clear();
+                    }
+                    part.push((x, y))
+                }
+                _ => fill.parts.push(vec![(x, y)]),
+            }
+        }
+    }
+
+    /* Arc around (cx, cy) starting at the given heading from the centre, clockwise for a positive
+    angle */
+    fn add_arc(&mut self, cx: f32, cy: f32, radius: f32, start: f32, angle: f32) {
+        if angle.abs() >= 360.0 {
+            self.add_circle(cx, cy, radius);
+            return;
+        }
+        self.extend_bounds(cx - radius, cy - radius, cx + radius, cy + radius);
+        let arc = Primitive::Arc {
+            centre: (cx, cy),
+            radius,
+            start,
+            angle,
+            pen: self.turtle().pen(),
+        };
+        self.draw(arc);
+    }
+
+    fn add_circle(&mut self, cx: f32, cy: f32, radius: f32) {
+        self.extend_bounds(cx - radius, cy - radius, cx + radius, cy + radius);
+        let circle = Primitive::Circle {
+            centre: (cx, cy),
+            radius,
+            pen: self.turtle().pen(),
+        };
+        self.draw(circle);
+    }
+
+    /* A shape drawn around the turtle is a part of its own in the filled shapes, the path of the
+    turtle goes on from where it is */
+    fn add_fill_outline(&mut self, outline: Vec<(f32, f32)>) {
+        let here = (self.turtle().x, self.turtle().y);
+        for fill in &mut self.fills {
+            fill.parts.push(outline.clone());
+            fill.parts.push(vec![here]);
+        }
+    }
+
+    /* Arc centred on the turtle, starting ahead of it, the turtle doesn't move */
+    fn arc(&mut self, angle: f32, radius: f32) {
+        let t = self.turtle();
+        if t.pen_down {
+            let (x, y, heading) = (t.x, t.y, t.heading);
+            self.add_arc(x, y, radius, heading, angle);
+            self.add_fill_outline(scene::arc_points((x, y), radius, heading, angle));
+        }
+    }
+
+    fn circle(&mut self, radius: f32) {
+        let t = self.turtle();
+        if t.pen_down {
+            let (x, y) = (t.x, t.y);
+            self.add_circle(x, y, radius);
+            self.add_fill_outline(scene::arc_points((x, y), radius, 0.0, 360.0));
+        }
+    }
+
+    /* Ellipse centred on the turtle, radius along the heading is the second one */
+    fn ellipse(&mut self, crosswise: f32, along: f32) {
+        let t = self.turtle();
+        if !t.pen_down {
+            return;
+        }
+        let (x, y) = (t.x, t.y);
+        let ellipse = Primitive::Ellipse {
+            centre: (x, y),
+            crosswise,
+            along,
+            heading: t.heading,
+            pen: t.pen(),
+        };
+        self.draw(ellipse);
+        let r = crosswise.max(along);
+        self.extend_bounds(x - r, y - r, x + r, y + r);
+        let heading = self.turtle().heading;
+        self.add_fill_outline(scene::ellipse_points((x, y), crosswise, along, heading));
+    }
+
+    /* The turtle walks along an arc, turning right for a positive angle. Its new position is
+    computed from the centre of the arc, so it doesn't drift like many small steps do */
+    fn arc2(&mut self, angle: f32, radius: f32) -> Result<(), LogoError> {
+        let (x, y, heading, pen_down) = {
+            let t = self.turtle();
+            (t.x, t.y, t.heading, t.pen_down)
+        };
+        let side = if angle < 0.0 { -90.0 } else { 90.0 };
+        let (cx, cy) = scene::point_at(x, y, heading + side, radius);
+        let start = heading - side;
+        let (new_x, new_y) = scene::point_at(cx, cy, start + angle, radius);
+        if self.mode == BoundaryMode::Fence && !self.inside(new_x, new_y) {
+            return Err(LogoError::runtime(format!(
+                "turtle out of bounds at [{} {}]",
+                new_x, new_y
+            )));
+        }
+        if pen_down {
+            self.add_arc(cx, cy, radius, start, angle);
+        }
+        // filled shapes follow the arc in steps of at most 5 degrees
+        let steps = (angle.abs() / 5.0).ceil() as usize;
+        for i in 1..=steps {
+            let (px, py) = scene::point_at(cx, cy, start + angle * i as f32 / steps as f32, radius);
+            self.add_fill_point(px, py, pen_down);
+        }
+        // arcs are not split at the edges, only the turtle wraps around
+        let (new_x, new_y) = match self.mode {
+            BoundaryMode::Wrap => self.wrap_position(new_x, new_y),
+            _ => (new_x, new_y),
+        };
+        let t = self.turtle_mut();
+        t.x = new_x;
+        t.y = new_y;
+        self.setheading(heading + angle);
+        Ok(())
+    }
+
+    fn setx(&mut self, x: f32) -> Result<(), LogoError> {
+        self.setpos(x, self.turtle().y)
+    }
+
+    fn sety(&mut self, y: f32) -> Result<(), LogoError> {
+        self.setpos(self.turtle().x, y)
+    }
+
+    fn setheading(&mut self, heading: f32) {
+        self.turtle_mut().heading = heading.rem_euclid(360.0);
+    }
+
+    /* Heading the turtle would need to face the point */
+    fn towards(&self, x: f32, y: f32) -> f32 {
+        let t = self.turtle();
+        (x - t.x).atan2(y - t.y).to_degrees().rem_euclid(360.0)
+    }
+
+    fn distance(&self, x: f32, y: f32) -> f32 {
+        let t = self.turtle();
+        (x - t.x).hypot(y - t.y)
+    }
+
+    fn home(&mut self) -> Result<(), LogoError> {
+        self.setpos(0.0, 0.0)?;
+        self.setheading(0.0);
+        Ok(())
+    }
+
+    fn forward(&mut self, dist: f32) -> Result<(), LogoError> {
+        let (new_x, new_y) = self.calculate_new_position(dist);
+        self.setpos(new_x, new_y)
+    }
+
+    fn backward(&mut self, dist: f32) -> Result<(), LogoError> {
+        self.forward(-dist)
     }
 
     fn right(&mut self, angle: f32) {
-        self.angle += angle;
-        // println!("image-right {}", self.angle);
+        self.setheading(self.turtle().heading + angle);
     }
 
     fn left(&mut self, angle: f32) {
-        self.angle -= angle;
-        // println!("image-left {}", self.angle);
-    }
-
-    fn add_turtles_to_svg(&mut self) {
-        for (x, y, v) in &self.turtles {
-            if *v {
-                let turtle = format!(
-                    "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" stroke=\"{}\" stroke-width=\"{}\" fill=\"{}\" />\n",
-                    x, y, self.turtle_size, self.pen_color, self.pen_width, self.turtle_color
-                );
-                self.svg.push_str(&turtle);
+        self.setheading(self.turtle().heading - angle);
+    }
+
+    /* Everything drawn so far, without the turtles */
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    /* The visible turtles, drawn on top of the scene */
+    pub fn visible_turtles(&self) -> Vec<Primitive> {
+        self.turtles
+            .values()
+            .filter(|t| t.visible)
+            .map(|t| Primitive::Turtle {
+                position: (t.x, t.y),
+                heading: t.heading,
+                shape: t.shape,
+                size: self.turtle_size,
+                pen: t.pen(),
+                fill: t.fill_color.clone(),
+            })
+            .collect()
+    }
+
+    /* Writes the drawing as SVG, replaying it if it is animated */
+    fn svg(&self) -> svg::Svg<'_> {
+        let svg = svg::Svg::new(self.width, self.height, self.svg_options);
+        match &self.svg_animation {
+            Some(animation) => svg.animated(animation),
+            None => svg,
+        }
+    }
+
+    /* The drawing so far with the turtles on top */
+    fn svg_body(&self) -> String {
+        self.svg().body(&self.scene, &self.visible_turtles())
+    }
+
+    /* The part of the drawing that is saved: x, y, width and height in SVG pixels */
+    fn view_box(&self) -> (f32, f32, f32, f32) {
+        let mut bounds = self.bounds;
+        for turtle in self.visible_turtles() {
+            let Primitive::Turtle {
+                position: (x, y),
+                size,
+                ..
+            } = turtle
+            else {
+                continue;
+            };
+            bounds = Some(match bounds {
+                Some((a, b, c, d)) => (
+                    a.min(x - size),
+                    b.min(y - size),
+                    c.max(x + size),
+                    d.max(y + size),
+                ),
+                None => (x - size, y - size, x + size, y + size),
+            });
+        }
+        match (self.autofit, bounds) {
+            (true, Some((x1, y1, x2, y2))) => {
+                // the top left corner in SVG pixels
+                let (x, y) = (self.width / 2.0 + x1, self.height / 2.0 - y2);
+                (
+                    x - AUTOFIT_MARGIN,
+                    y - AUTOFIT_MARGIN,
+                    x2 - x1 + 2.0 * AUTOFIT_MARGIN,
+                    y2 - y1 + 2.0 * AUTOFIT_MARGIN,
+                )
             }
+            _ => (0.0, 0.0, self.width, self.height),
         }
     }
 
+    /* A whole SVG file around the body. Its size comes from the canvas, or from everything
+    drawn by now in autofit mode, so frames recorded earlier all get the final size */
+    fn svg_document(&self, body: &str) -> String {
+        let fitted = self.autofit.then(|| self.view_box());
+        self.svg().document(&self.scene, fitted, body)
+    }
+
+    /* Write every line as its own element instead of joining them into paths */
+    pub fn set_merge_lines(&mut self, merge: bool) {
+        self.svg_options.merge_lines = merge;
+    }
+
+    /* Round SVG coordinates to so many decimal places */
+    pub fn set_precision(&mut self, digits: usize) {
+        self.svg_options.precision = Some(digits);
+    }
+
+    /* Make the SVG replay the drawing, drawing a line or showing an element every step
+    milliseconds */
+    pub fn animate_svg(&mut self, step_ms: u32) {
+        self.svg_animation = Some(svg::Animation::new(step_ms, self.scene.len()));
+    }
+
+    /* Start taking a snapshot of the image every so many turtle commands and at every wait */
+    pub fn record_frames(&mut self, every: usize, delay_ms: u32) {
+        self.frames = Some(Frames {
+            every,
+            delay_ms,
+            since_last: 0,
+            shown_in: vec![0; self.scene.len()],
+            cleared: vec![],
+            recorded: vec![],
+        });
+    }
+
+    /* Called after every turtle command */
+    fn tick(&mut self) {
+        let Some(frames) = &self.frames else {
+            return;
+        };
+        if frames.since_last + 1 >= frames.every {
+            self.add_frame(0);
+        } else if let Some(frames) = &mut self.frames {
+            frames.since_last += 1;
+        }
+    }
+
+    /* Snapshot of the image, shown for longer when the program waits */
+    fn add_frame(&mut self, extra_ms: u32) {
+        let turtles = self.visible_turtles();
+        // lines after the frame are drawn after it, so the frame doesn't show them
+        self.joinable = false;
+        if let Some(frames) = &mut self.frames {
+            frames.since_last = 0;
+            frames.recorded.push(Frame {
+                drawing: frames.cleared.len(),
+                turtles,
+                delay_ms: frames.delay_ms + extra_ms,
+            });
+        }
+    }
+
+    fn wait(&mut self, seconds: f32) {
+        let ms = (seconds * 1000.0) as u32;
+        // lines after the wait are drawn after it, not as part of the lines before
+        self.joinable = false;
+        // no need to really wait when the waiting is recorded in the animation
+        if let Some(animation) = &mut self.svg_animation {
+            animation.wait(ms);
+        } else if self.frames.is_some() {
+            self.add_frame(ms);
+        } else {
+            thread::sleep(Duration::from_millis(ms as u64));
+        }
+    }
+
+    /* Frames recorded so far as whole SVG files, ending with the finished image. Each file is
+    only written when it is reached */
+    fn frame_documents(&self) -> impl Iterator<Item = (String, u32)> + '_ {
+        let recorded = self.frames.iter().flat_map(move |frames| {
+            frames.recorded.iter().enumerate().map(move |(i, frame)| {
+                let (scene, shown_in) = match frames.cleared.get(frame.drawing) {
+                    Some((scene, shown_in)) => (scene, shown_in),
+                    None => (&self.scene, &frames.shown_in),
+                };
+                let body =
+                    self.svg()
+                        .body_where(scene, |index| shown_in[index] <= i, &frame.turtles);
+                (self.svg_document(&body), frame.delay_ms)
+            })
+        });
+        let last = (self.svg_document(&self.svg_body()), FINAL_FRAME_MS);
+        recorded.chain(std::iter::once(last))
+    }
+
     pub fn save_svg(&mut self, filename: &str) {
-        self.saveturtle();
-        self.add_turtles_to_svg();
-        self.svg.push_str("</svg>");
+        let svg = self.svg_document(&self.svg_body());
         let mut file = File::create(filename).expect("Unable to create SVG file");
-        file.write_all(self.svg.as_bytes())
+        file.write_all(svg.as_bytes())
             .expect("Unable to write SVG content to file");
     }
+
+    /* What the pen drew, scaled to fit the paper and ordered for as little pen-up travel as
+    possible. Fills, labels and turtles are left out */
+    fn plot(&self, paper: plotter::Paper) -> Vec<plotter::Stroke> {
+        let (x, y, width, height) = self.view_box();
+        // the bottom left corner in Logo coordinates
+        let (left, bottom) = (x - self.width / 2.0, self.height / 2.0 - y - height);
+        let strokes = plotter::strokes(self.scene.primitives());
+        plotter::optimise(plotter::to_paper(
+            &strokes,
+            (left, bottom, width, height),
+            paper,
+        ))
+    }
+
+    pub fn save_hpgl(&self, filename: &str, paper: plotter::Paper) {
+        let mut file = File::create(filename).expect("Unable to create HPGL file");
+        file.write_all(plotter::hpgl(&self.plot(paper)).as_bytes())
+            .expect("Unable to write HPGL file");
+    }
+
+    pub fn save_gcode(&self, filename: &str, paper: plotter::Paper, feed_rate: f32) {
+        let mut file = File::create(filename).expect("Unable to create G-code file");
+        file.write_all(plotter::gcode(&self.plot(paper), paper, feed_rate).as_bytes())
+            .expect("Unable to write G-code file");
+    }
+
+    /* Antialiased bitmap of the same image, scale 2 gives twice as many pixels. Fails without
+    writing anything if the image is too big */
+    pub fn save_png(&mut self, filename: &str, scale: f32) -> Result<(), String> {
+        let pixmap = raster::render(&self.svg_document(&self.svg_body()), scale)?;
+        pixmap.save_png(filename).expect("Unable to write PNG file");
+        Ok(())
+    }
+
+    /* Fails without writing anything if the image is too big for a GIF */
+    pub fn save_gif(&mut self, filename: &str, scale: f32) -> Result<(), String> {
+        let (_, _, width, height) = self.view_box();
+        raster::gif_size(raster::pixels(width, scale), raster::pixels(height, scale))?;
+        let frames = self
+            .frame_documents()
+            .map(|(svg, delay)| Ok((raster::render(&svg, scale)?, delay)))
+            .collect::<Result<Vec<_>, String>>()?;
+        raster::save_gif(&frames, filename)
+    }
+
+    /* Every frame as its own PNG file: tree.png gives tree-0001.png, tree-0002.png, ... */
+    pub fn save_frames(&mut self, filename: &str, scale: f32) -> Result<(), String> {
+        let path = Path::new(filename);
+        let stem = path.with_extension("");
+        for (i, (svg, _)) in self.frame_documents().enumerate() {
+            let name = format!("{}-{:04}.png", stem.display(), i + 1);
+            raster::render(&svg, scale)?
+                .save_png(&name)
+                .expect("Unable to write PNG file");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::process;
+    use crate::parser::parse_program;
+
+    fn run(input: &str, env: &mut Environment) -> Result<(), LogoError> {
+        run_on(input, env, &mut Image::new(700.0, 700.0))
+    }
+
+    fn run_on(input: &str, env: &mut Environment, image: &mut Image) -> Result<(), LogoError> {
+        let mut tokens = process(input)?;
+        let ast = parse_program(&mut tokens, &mut HashMap::new())?;
+        eval_program(ast, &mut HashMap::new(), env, image)
+    }
+
+    fn number(env: &mut Environment, name: &str) -> f32 {
+        env.get(name).unwrap().get_number().unwrap()
+    }
+
+    fn boolean(env: &mut Environment, name: &str) -> bool {
+        env.get(name).unwrap().get_bool().unwrap()
+    }
+
+    /* The drawing as SVG elements, without the turtles */
+    fn drawing_svg(image: &Image) -> String {
+        image.svg().drawing(image.scene())
+    }
+
+    #[test]
+    fn test_stop_ends_only_the_procedure() {
+        let mut env = Environment::new();
+        let input = "make \"n 0
+            to counter repeat 10 [ if 4 < :n [ stop ] make \"n :n + 1 ] make \"n 100 end
+            counter make \"after 1";
+        run(input, &mut env).unwrap();
+        assert_eq!(number(&mut env, "n"), 5.0);
+        assert_eq!(number(&mut env, "after"), 1.0);
+    }
+
+    #[test]
+    fn test_errors_bubble_up_from_procedures() {
+        let mut env = Environment::new();
+        let input = "to bad show :nope end\nto outer bad make \"reached 1 end outer";
+        let err = run(input, &mut env).unwrap_err();
+        assert_eq!(
+            err,
+            LogoError::runtime("variable nope was not declared").at(Span {
+                line: 1,
+                column: 13,
+                len: 5
+            })
+        );
+        assert_eq!(
+            err.render(input),
+            "runtime error at line 1, column 13: variable nope was not declared\n   \
+             1 | to bad show :nope end\n     |             ^^^^^"
+        );
+        assert!(env.get("reached").is_err());
+
+        // errors without a place of their own point to the command
+        let err = run("fd 10\nsetpensize 0 - 1", &mut env).unwrap_err();
+        assert_eq!(
+            err.span(),
+            Some(Span {
+                line: 2,
+                column: 1,
+                len: 10
+            })
+        );
+    }
+
+    #[test]
+    fn test_call_depth_limit() {
+        // the stack main gives the interpreter, the default one for tests is too small
+        let deep = |input: &'static str| {
+            thread::Builder::new()
+                .stack_size(INTERPRETER_STACK)
+                .spawn(move || run(input, &mut Environment::new()))
+                .unwrap()
+                .join()
+                .unwrap()
+        };
+        assert!(matches!(
+            deep("to f :n f :n + 1 end f 1"),
+            Err(LogoError::Runtime(msg, _)) if msg == "f: too many nested procedure calls"
+        ));
+        assert_eq!(deep("to f :n if :n > 0 [f :n - 1] end f 4000"), Ok(()));
+        assert_eq!(
+            deep("to g :n if :n = 0 [output 0] output 1 + g :n - 1 end make \"x g 4000"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_stop_and_output_at_top_level() {
+        let mut env = Environment::new();
+        assert!(run("repeat 3 [ stop ]", &mut env).is_err());
+        assert!(run("output 3", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_procedures_used_before_their_to() {
+        let mut env = Environment::new();
+        let input = "to iseven :n if :n = 0 [output true] output isodd :n - 1 end
+            to isodd :n if :n = 0 [output false] output iseven :n - 1 end
+            to area :r output 3 * square :r end
+            to square :x output :x * :x end
+            make \"even iseven 10 make \"odd isodd 10 make \"a area 2 + 1";
+        run(input, &mut env).unwrap();
+        assert!(boolean(&mut env, "even"));
+        assert!(!boolean(&mut env, "odd"));
+        assert_eq!(number(&mut env, "a"), 27.0);
+    }
+
+    #[test]
+    fn test_variable_names() {
+        let mut env = Environment::new();
+        let input = "make \"x1 5 make \"my_var :x1 + 1 make \"Size 3
+            to sq :Side output :side * :SIDE end
+            make \"a :size make \"b thing \"SIZE make \"c sq 4 make \"d :x1-1";
+        run(input, &mut env).unwrap();
+        assert_eq!(number(&mut env, "my_var"), 6.0);
+        assert_eq!(number(&mut env, "a"), 3.0);
+        assert_eq!(number(&mut env, "b"), 3.0);
+        assert_eq!(number(&mut env, "c"), 16.0);
+        assert_eq!(number(&mut env, "d"), 4.0);
+    }
+
+    #[test]
+    fn test_words() {
+        let mut env = Environment::new();
+        let input = "make \"w word \"tur \"tle
+            make \"f first :w make \"bf butfirst :w make \"l last :w make \"bl bl :w
+            make \"n count :w make \"same equalp \"TURTLE :w make \"sum word 1 2";
+        run(input, &mut env).unwrap();
+        let word = |env: &mut Environment, name: &str| env.get(name).unwrap().to_string();
+        assert_eq!(word(&mut env, "w"), "turtle");
+        assert_eq!(word(&mut env, "f"), "t");
+        assert_eq!(word(&mut env, "bf"), "urtle");
+        assert_eq!(word(&mut env, "l"), "e");
+        assert_eq!(word(&mut env, "bl"), "turtl");
+        assert_eq!(number(&mut env, "n"), 6.0);
+        assert!(boolean(&mut env, "same"));
+        assert_eq!(number(&mut env, "sum"), 12.0);
+        assert!(run("show first \"", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_lists() {
+        let mut env = Environment::new();
+        let input = "make \"l [1 [2 3] abc]
+            to rev :l if emptyp :l [ output [] ] output lput first :l rev bf :l end
+            make \"r rev :l make \"s se :l [x] make \"i item 2 :l
+            make \"m memberp [2 3] :l make \"n count fput 0 :l
+            make \"j item 1 + 1 [a b c] make \"k (count [a b]) + 1";
+        run(input, &mut env).unwrap();
+        let value = |env: &mut Environment, name: &str| env.get(name).unwrap().to_string();
+        assert_eq!(value(&mut env, "r"), "[abc [2 3] 1]");
+        assert_eq!(value(&mut env, "s"), "[1 [2 3] abc x]");
+        assert_eq!(value(&mut env, "i"), "[2 3]");
+        assert!(boolean(&mut env, "m"));
+        assert_eq!(number(&mut env, "n"), 4.0);
+        assert_eq!(value(&mut env, "j"), "b");
+        assert_eq!(number(&mut env, "k"), 3.0);
+        assert!(run("show item 4 :l", &mut env).is_err());
+        // the input of count is the whole sum, and a list can't be added to
+        assert!(run("show count [a b] + 1", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_comparisons_and_booleans() {
+        let mut env = Environment::new();
+        let input = "make \"a (1 < 2) and 3 >= 3 or false
+            make \"b not (2 <> 2) make \"c and lessp 1 2 greaterp 1 2
+            make \"d 2 <= 1 or equalp \"true true
+            make \"e not 1 = 2 and false make \"f and 1 < 2 3 < 4";
+        run(input, &mut env).unwrap();
+        assert!(boolean(&mut env, "a"));
+        assert!(boolean(&mut env, "b"));
+        assert!(!boolean(&mut env, "c"));
+        assert!(boolean(&mut env, "d"));
+        assert!(!boolean(&mut env, "e"));
+        assert!(boolean(&mut env, "f"));
+        assert!(matches!(
+            run("if 1 [ show 1 ]", &mut env),
+            Err(LogoError::Runtime(msg, _)) if msg == "expected true or false, got 1"
+        ));
+    }
+
+    #[test]
+    fn test_math() {
+        let mut env = Environment::new();
+        let input = "make \"a (sqrt 16) + power 2 3 make \"b round 100 * sin 30
+            make \"c arctan 1 make \"d remainder -7 2 make \"e modulo -7 2
+            make \"f int 3.7 + abs -2 make \"g max 3 min 10 4 make \"h round pi * 100
+            make \"i sqrt 4 + 5";
+        run(input, &mut env).unwrap();
+        assert_eq!(number(&mut env, "a"), 12.0);
+        assert_eq!(number(&mut env, "b"), 50.0);
+        assert_eq!(number(&mut env, "c"), 45.0);
+        assert_eq!(number(&mut env, "d"), -1.0);
+        assert_eq!(number(&mut env, "e"), 1.0);
+        assert_eq!(number(&mut env, "f"), 5.0);
+        assert_eq!(number(&mut env, "g"), 4.0);
+        assert_eq!(number(&mut env, "h"), 314.0);
+        assert_eq!(number(&mut env, "i"), 3.0);
+        assert!(run("show sqrt -1", &mut env).is_err());
+        assert!(run("show exp 100", &mut env).is_err());
+        assert!(run("show power -1 0.5", &mut env).is_err());
+        assert!(run("show power 10 39", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_absolute_positioning() {
+        let mut env = Environment::new();
+        let mut image = Image::new(700.0, 700.0);
+        run_on("setxy 100 50 seth 90 fd 10", &mut env, &mut image).unwrap();
+        assert_eq!(
+            (image.turtle().x, image.turtle().y, image.turtle().heading),
+            (110.0, 50.0, 90.0)
+        );
+        assert!(drawing_svg(&image).contains("L 450 300 L 460 300\""));
+
+        // a minus glued to a number after a space is the sign of the next input
+        run_on("setxy 10 -40 setxy -10 -40 - 5", &mut env, &mut image).unwrap();
+        assert_eq!((image.turtle().x, image.turtle().y), (-10.0, -45.0));
+        run_on("setxy 10-4 10 - 4", &mut env, &mut image).unwrap();
+        assert_eq!((image.turtle().x, image.turtle().y), (6.0, 6.0));
+        run_on(
+            "to go :x :y setxy :x :y end go 1 -2 to later again 3 -4 end to again :x :y go :x :y end later",
+            &mut env,
+            &mut image,
+        )
+        .unwrap();
+        assert_eq!((image.turtle().x, image.turtle().y), (3.0, -4.0));
+
+        run_on("pu setpos [-20 30] setx 5 lt 135", &mut env, &mut image).unwrap();
+        assert_eq!(
+            (image.turtle().x, image.turtle().y, image.turtle().heading),
+            (5.0, 30.0, 315.0)
+        );
+
+        run_on("home", &mut env, &mut image).unwrap();
+        assert_eq!(
+            (image.turtle().x, image.turtle().y, image.turtle().heading),
+            (0.0, 0.0, 0.0)
+        );
+        assert!(run("setpos [1 2 3]", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_turtle_queries() {
+        let mut env = Environment::new();
+        let input = "setxy 30 40 make \"p pos make \"d distance [0 0] make \"t towards [30 0]
+            pu rt 45 make \"h heading make \"down pendownp setpos :p
+            make \"x xcor + ycor make \"c pencolor";
+        run(input, &mut env).unwrap();
+        assert_eq!(env.get("p").unwrap().to_string(), "[30 40]");
+        assert_eq!(number(&mut env, "d"), 50.0);
+        assert_eq!(number(&mut env, "t"), 180.0);
+        assert_eq!(number(&mut env, "h"), 45.0);
+        assert!(!boolean(&mut env, "down"));
+        assert_eq!(number(&mut env, "x"), 70.0);
+        assert_eq!(env.get("c").unwrap().to_string(), "black");
+    }
+
+    #[test]
+    fn test_turtles_keep_their_own_state() {
+        let mut env = Environment::new();
+        let mut image = Image::new(700.0, 700.0);
+        let input = "rt 90 setcolor red pu fd 50 setturtle 1 fd 10 setturtle 0 make \"h heading";
+        run_on(input, &mut env, &mut image).unwrap();
+        assert_eq!(number(&mut env, "h"), 90.0);
+        let second = &image.turtles[&1];
+        assert_eq!((second.x, second.y, second.heading), (0.0, 10.0, 0.0));
+        assert!(second.pen_down);
+        assert_eq!(second.pen_color, "black");
+        assert!(drawing_svg(&image).contains("stroke=\"black\""));
+        assert!(!drawing_svg(&image).contains("stroke=\"red\""));
+        assert!(run("setshape \"square", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_tell_ask_each() {
+        let mut env = Environment::new();
+        let mut image = Image::new(700.0, 700.0);
+        let input = "tell [0 5 2] fd 10 make \"w who make \"all turtles
+            ask 5 [rt 90 fd 20 make \"x round xcor make \"y round ycor]
+            make \"sum 0 each [make \"sum :sum + who]
+            setturtle 7 make \"last who";
+        run_on(input, &mut env, &mut image).unwrap();
+        assert_eq!(env.get("w").unwrap().to_string(), "[0 5 2]");
+        assert_eq!(env.get("all").unwrap().to_string(), "[0 2 5]");
+        assert_eq!((number(&mut env, "x"), number(&mut env, "y")), (20.0, 10.0));
+        assert_eq!(number(&mut env, "sum"), 7.0);
+        assert_eq!(number(&mut env, "last"), 7.0);
+        assert_eq!(image.turtles[&2].y, 10.0);
+        assert_eq!(image.turtles[&0].heading, 0.0);
+        assert!(run("tell []", &mut env).is_err());
+        assert!(run("ask -1 [fd 10]", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_pen_styles() {
+        let mut env = Environment::new();
+        let mut image = Image::new(700.0, 700.0);
+        let input = "setpensize 4 setpenpattern [5 3] setlinecap \"round
+            setlinejoin \"bevel setpenopacity 0.5 fd 10";
+        run_on(input, &mut env, &mut image).unwrap();
+        assert!(drawing_svg(&image).contains(
+            "stroke=\"black\" stroke-width=\"4\" stroke-dasharray=\"5 3\" \
+             stroke-linecap=\"round\" stroke-linejoin=\"bevel\" stroke-opacity=\"0.5\""
+        ));
+
+        run_on(
+            "setwidth [2 2] setpenpattern [] fd 10",
+            &mut env,
+            &mut image,
+        )
+        .unwrap();
+        assert!(drawing_svg(&image).contains("stroke-width=\"2\" stroke-linecap"));
+        assert!(run("setpenopacity 2", &mut env).is_err());
+        assert!(run("setlinecap \"pointy", &mut env).is_err());
+        assert!(run("setpenpattern [1 -1]", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_colors() {
+        let mut env = Environment::new();
+        let mut image = Image::new(700.0, 700.0);
+        let input = "setpencolor [255 136 0] make \"a pencolor setpc \"#F80 make \"b pencolor
+            setpc \"salmon make \"c pencolor setpc 4 make \"d pencolor
+            setpalette 20 [1 2 3] setpc 20 make \"e pencolor
+            make \"i 10 setpc se :i [0 0] make \"f pencolor setbg \"navy";
+        run_on(input, &mut env, &mut image).unwrap();
+        let color = |env: &mut Environment, name| env.get(name).unwrap().to_string();
+        assert_eq!(color(&mut env, "a"), "#ff8800");
+        assert_eq!(color(&mut env, "b"), "#f80");
+        assert_eq!(color(&mut env, "c"), "salmon");
+        assert_eq!(color(&mut env, "d"), "#ff0000");
+        assert_eq!(color(&mut env, "e"), "#010203");
+        assert_eq!(color(&mut env, "f"), "#0a0000");
+        assert_eq!(image.scene().background(), Some("navy"));
+
+        assert!(run("setpc [256 0 0]", &mut env).is_err());
+        assert!(run("setpc \"nocolor", &mut env).is_err());
+        assert!(run("setpc 99", &mut env).is_err());
+        assert!(run("setpalette 3 [0 0 0]", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_filled_shapes() {
+        let mut env = Environment::new();
+        let mut image = Image::new(700.0, 700.0);
+        let input = "setfillrule \"evenodd filled red [repeat 4 [fd 10 rt 90]]
+            pu setxy 100 0 pd setfillcolor [0 0 255] fill [fd 10 pu fd 10 pd rt 90 fd 10]";
+        run_on(input, &mut env, &mut image).unwrap();
+        assert!(drawing_svg(&image).starts_with(
+            "<path d=\"M 350 350 L 350 340 L 360 340 L 360 350 L 350 350 Z\" \
+             fill=\"red\" fill-rule=\"evenodd\" stroke=\"none\" />\n<path d=\"M 350 350 L 350 340"
+        ));
+        assert!(drawing_svg(&image)
+            .contains("<path d=\"M 450 350 L 450 340 Z M 450 330 L 460 330 Z\" fill=\"#0000ff\""));
+        assert!(image.fills.is_empty());
+        assert!(run("setfillrule \"odd", &mut env).is_err());
+
+        // shapes drawn around the turtle are filled too, apart from the path it walks
+        let mut image = Image::new(100.0, 100.0);
+        run_on(
+            "filled \"red [circle 10 fd 5 rt 90 fd 5]",
+            &mut env,
+            &mut image,
+        )
+        .unwrap();
+        let Primitive::Fill { parts, .. } = image.scene().primitives().next().unwrap() else {
+            panic!("expected the fill below the outlines");
+        };
+        assert_eq!(parts.len(), 2);
+        assert_eq!((parts[0].len(), parts[0][0]), (73, (0.0, 10.0)));
+        assert_eq!(parts[1], vec![(0.0, 0.0), (0.0, 5.0), (5.0, 5.0)]);
+        let mut image = Image::new(100.0, 100.0);
+        run_on(
+            "filled \"red [arc 90 10 ellipse 5 10]",
+            &mut env,
+            &mut image,
+        )
+        .unwrap();
+        assert!(matches!(
+            image.scene().primitives().next(),
+            Some(Primitive::Fill { parts, .. }) if parts.len() == 2
+        ));
+
+        let input = "fd 10 setpc \"red fd 10 filled \"blue [clearscreen rt 90 fd 10]";
+        let mut image = Image::new(700.0, 700.0);
+        assert!(matches!(
+            run_on(input, &mut env, &mut image),
+            Err(LogoError::Runtime(msg, _)) if msg == "clearscreen can't be used inside filled"
+        ));
+        assert!(image.fills.is_empty());
+        assert_eq!(image.scene().len(), 2);
+    }
+
+    #[test]
+    fn test_arcs_and_circles() {
+        let mut env = Environment::new();
+        let mut image = Image::new(700.0, 700.0);
+        run_on("arc 90 50 circle 30 ellipse 20 10", &mut env, &mut image).unwrap();
+        assert!(drawing_svg(&image)
+            .contains("<path d=\"M 350 300 A 50 50 0 0 1 400 350\" stroke=\"black\""));
+        assert!(drawing_svg(&image).contains("<circle cx=\"350\" cy=\"350\" r=\"30\""));
+        assert!(drawing_svg(&image).contains("<ellipse cx=\"350\" cy=\"350\" rx=\"20\" ry=\"10\""));
+        assert_eq!(
+            (image.turtle().x, image.turtle().y, image.turtle().heading),
+            (0.0, 0.0, 0.0)
+        );
+
+        run_on("arc2 90 100", &mut env, &mut image).unwrap();
+        assert_eq!(
+            (image.turtle().x, image.turtle().y, image.turtle().heading),
+            (100.0, 100.0, 90.0)
+        );
+        run_on("home arc2 -90 10", &mut env, &mut image).unwrap();
+        assert_eq!(
+            (image.turtle().x, image.turtle().y, image.turtle().heading),
+            (-10.0, 10.0, 270.0)
+        );
+        // many arcs in a row still end up exactly where they started
+        run_on("home repeat 36 [arc2 10 100]", &mut env, &mut image).unwrap();
+        let t = image.turtle();
+        assert!(t.x.abs() < 1e-3 && t.y.abs() < 1e-3 && t.heading.abs() < 1e-3);
+        assert!(run("circle -5", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut env = Environment::new();
+        let mut image = Image::new(700.0, 700.0);
+        let input = "setlabelheight 20 setlabelfont [Times New Roman] label \"hello
+            rt 90 setpc red label \"x<1&y label [Fig. 1: Hello, World!]";
+        run_on(input, &mut env, &mut image).unwrap();
+        assert!(drawing_svg(&image).contains(
+            "<text x=\"350\" y=\"350\" font-family=\"Times New Roman\" font-size=\"20\" \
+             fill=\"black\">hello</text>"
+        ));
+        assert!(drawing_svg(&image)
+            .contains("fill=\"red\" transform=\"rotate(90 350 350)\">x&lt;1&amp;y</text>"));
+        assert!(drawing_svg(&image).contains(">Fig. 1: Hello, World!</text>"));
+        assert!(run("setlabelheight 0", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_boundary_modes() {
+        let mut env = Environment::new();
+        let mut image = Image::new(100.0, 100.0);
+        run_on("setxy 0 80", &mut env, &mut image).unwrap();
+        assert!(drawing_svg(&image).contains("M 50 50 L 50 0\""));
+        assert_eq!((image.turtle().x, image.turtle().y), (0.0, 80.0));
+
+        image.clear();
+        run_on("wrap", &mut env, &mut image).unwrap();
+        assert_eq!((image.turtle().x, image.turtle().y), (0.0, -20.0));
+        run_on("home setxy 80 0", &mut env, &mut image).unwrap();
+        assert!(drawing_svg(&image).contains("50 50 L 100 50\""));
+        assert!(drawing_svg(&image).contains("M 0 50 L 30 50\""));
+        assert_eq!((image.turtle().x, image.turtle().y), (-20.0, 0.0));
+
+        run_on("fence home", &mut env, &mut image).unwrap();
+        assert!(run_on("fd 60", &mut env, &mut image).is_err());
+        assert_eq!((image.turtle().x, image.turtle().y), (0.0, 0.0));
+        assert!(run_on("fd 50", &mut env, &mut image).is_ok());
+
+        // a huge move wraps a limited number of times and still ends on the canvas
+        image.clear();
+        run_on(
+            "wrap home rt 30 fd 100000000 fd power 10 30",
+            &mut env,
+            &mut image,
+        )
+        .unwrap();
+        assert!(image.scene().len() <= 2 * MAX_WRAPS + 2);
+        let (x, y) = (image.turtle().x, image.turtle().y);
+        assert!(x.abs() <= 50.0 && y.abs() <= 50.0);
+    }
+
+    #[test]
+    fn test_canvas_size_and_autofit() {
+        let mut env = Environment::new();
+        let mut image = Image::new(100.0, 100.0);
+        run_on(
+            "setxy 10 0 setcanvas 200 100 setxy 20 0",
+            &mut env,
+            &mut image,
+        )
+        .unwrap();
+        // what was drawn stays where it was, relative to the centre
+        assert!(drawing_svg(&image).starts_with("<path d=\"M 100 50 L 110 50 L 120 50\""));
+        assert!(run("setcanvas 0 10", &mut env).is_err());
+
+        let mut image = Image::new(100.0, 100.0);
+        image.set_autofit(true);
+        run_on("ht setxy 300 0 setbg \"white", &mut env, &mut image).unwrap();
+        assert!(drawing_svg(&image).contains("L 350 50\""));
+        assert_eq!(
+            image
+                .svg_document(&image.svg_body())
+                .lines()
+                .next()
+                .unwrap(),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"320\" height=\"20\" viewBox=\"40 40 320 20\">\
+             <rect x=\"40\" y=\"40\" width=\"320\" height=\"20\" fill=\"white\" />"
+        );
+    }
+
+    #[test]
+    fn test_png_rendering() {
+        let mut env = Environment::new();
+        let mut image = Image::new(100.0, 100.0);
+        run_on("ht setpensize 4 fd 40", &mut env, &mut image).unwrap();
+        let pixmap = raster::render(&image.svg_document(&image.svg_body()), 2.0).unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (200, 200));
+        let pixel = |x: u32, y: u32| pixmap.pixel(x, y).unwrap();
+        assert_eq!((pixel(100, 60).red(), pixel(100, 60).alpha()), (0, 255));
+        assert_eq!(pixel(20, 20).red(), 255);
+        // too big to fit in memory, nothing is written
+        let path = std::env::temp_dir().join("logo_test_huge.png");
+        let path = path.to_str().unwrap();
+        assert!(image.save_png(path, 200.0).is_err());
+        assert!(!Path::new(path).exists());
+    }
+
+    #[test]
+    fn test_animation_frames() {
+        let mut env = Environment::new();
+        let mut image = Image::new(100.0, 100.0);
+        image.record_frames(2, 100);
+        run_on("fd 10 rt 90 fd 10 wait 1 fd 10", &mut env, &mut image).unwrap();
+        let frames: Vec<(String, u32)> = image.frame_documents().collect();
+        let delays: Vec<u32> = frames.iter().map(|(_, delay)| *delay).collect();
+        assert_eq!(delays, vec![100, 1100, FINAL_FRAME_MS]);
+        let lines = |svg: &str| svg.matches(" L ").count();
+        assert_eq!(
+            frames.iter().map(|(svg, _)| lines(svg)).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert!(frames[0].0.contains("<circle cx=\"50\" cy=\"40\""));
+
+        // frames from before a clearscreen keep what was wiped
+        let mut image = Image::new(100.0, 100.0);
+        image.record_frames(1, 100);
+        run_on("fd 10 clearscreen rt 90 fd 20", &mut env, &mut image).unwrap();
+        let frames: Vec<String> = image.frame_documents().map(|(svg, _)| svg).collect();
+        assert_eq!(
+            frames.iter().map(|svg| lines(svg)).collect::<Vec<_>>(),
+            vec![1, 0, 1, 1]
+        );
+        assert!(frames[0].contains("M 50 50 L 50 40"));
+        assert!(frames[2].contains("M 50 40 L 70 40"));
+
+        let mut image = Image::new(70000.0, 100.0);
+        image.record_frames(2, 100);
+        run_on("fd 10", &mut env, &mut image).unwrap();
+        let path = std::env::temp_dir().join("logo-test-too-wide.gif");
+        let path = path.to_str().unwrap();
+        assert_eq!(
+            image.save_gif(path, 1.0),
+            Err(
+                "GIF images can be at most 65535 x 65535 pixels, this one would be 70000 x 100"
+                    .into()
+            )
+        );
+        assert!(!Path::new(path).exists());
+    }
+
+    #[test]
+    fn test_scene_primitives() {
+        let mut env = Environment::new();
+        let mut image = Image::new(100.0, 100.0);
+        let input = "setbg \"navy fd 10 fd 10 pu fd 5 pd setpc \"red circle 5 label \"hi
+            pu setxy 0 30 pd filled \"blue [setxy 5 30]";
+        run_on(input, &mut env, &mut image).unwrap();
+        let scene = image.scene();
+        assert_eq!(scene.background(), Some("navy"));
+        let primitives: Vec<&Primitive> = scene.primitives().collect();
+        assert_eq!(primitives.len(), 5);
+        let Primitive::Polyline { points, pen } = primitives[0] else {
+            panic!("expected a polyline, got {:?}", primitives[0]);
+        };
+        assert_eq!(points, &vec![(0.0, 0.0), (0.0, 10.0), (0.0, 20.0)]);
+        assert_eq!((pen.color.as_str(), pen.width), ("black", 1.0));
+        assert!(matches!(
+            primitives[1],
+            Primitive::Circle { centre: (0.0, 25.0), radius: 5.0, pen } if pen.color == "red"
+        ));
+        assert!(matches!(primitives[2], Primitive::Label { text, .. } if text == "hi"));
+        // the fill goes below the outline drawn inside the filled block
+        assert!(matches!(primitives[3], Primitive::Fill { color, .. } if color == "blue"));
+        assert!(matches!(primitives[4], Primitive::Polyline { points, .. } if points.len() == 2));
+        assert!(matches!(
+            image.visible_turtles()[..],
+            [Primitive::Turtle {
+                position: (5.0, 30.0),
+                ..
+            }]
+        ));
+
+        run_on("clearscreen", &mut env, &mut image).unwrap();
+        assert!(image.scene().is_empty());
+        assert_eq!(image.scene().background(), Some("navy"));
+    }
+
+    #[test]
+    fn test_merged_lines() {
+        let mut env = Environment::new();
+        let mut image = Image::new(100.0, 100.0);
+        let input = "fd 10 rt 90 fd 10 setpc \"red fd 10 pu fd 10 pd fd 10";
+        run_on(input, &mut env, &mut image).unwrap();
+        let svg = drawing_svg(&image);
+        let lines: Vec<&str> = svg.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("<path d=\"M 50 50 L 50 40 L 60 40\" stroke=\"black\""));
+        assert!(lines[1].starts_with("<path d=\"M 60 40 L 70 40\" stroke=\"red\""));
+        assert!(lines[2].starts_with("<path d=\"M 80 40 L 90 40\""));
+        assert!(lines[2].ends_with("fill=\"none\" />"));
+
+        let mut image = Image::new(100.0, 100.0);
+        image.set_precision(1);
+        run_on("setxy 1.234 5.678 setxy 10 10", &mut env, &mut image).unwrap();
+        assert!(drawing_svg(&image).starts_with("<path d=\"M 50 50 L 51.2 44.3 L 60 40\""));
+
+        let mut image = Image::new(100.0, 100.0);
+        image.set_merge_lines(false);
+        run_on("fd 10 fd 10", &mut env, &mut image).unwrap();
+        assert_eq!(drawing_svg(&image).matches("<line").count(), 2);
+    }
+
+    #[test]
+    fn test_plotter_output() {
+        let mut env = Environment::new();
+        let mut image = Image::new(100.0, 100.0);
+        run_on(
+            "setxy 10 0 setxy 10 10 pu setxy -40 0 pd setxy -40 -40 \
+             pu setxy 10 20 pd setxy 10 30 setpc \"red setxy 20 30",
+            &mut env,
+            &mut image,
+        )
+        .unwrap();
+        let paper = plotter::Paper::parse("120x120").unwrap();
+        let strokes = image.plot(paper);
+        let points: Vec<Vec<(f32, f32)>> = strokes.iter().map(|s| s.points.clone()).collect();
+        // joined segments become one stroke, the one near the paper's corner goes first, reversed
+        assert_eq!(
+            points,
+            vec![
+                vec![(20.0, 20.0), (20.0, 60.0)],
+                vec![(60.0, 60.0), (70.0, 60.0), (70.0, 70.0)],
+                vec![(70.0, 80.0), (70.0, 90.0)],
+                vec![(70.0, 90.0), (80.0, 90.0)],
+            ]
+        );
+        assert_eq!(strokes[3].color, "red");
+        let hpgl = plotter::hpgl(&strokes);
+        assert!(hpgl.starts_with("IN;\nSP1;\nPU800,800;\nPD800,2400;\nPU2400,2400;\n"));
+        assert!(hpgl.ends_with("SP2;\nPU2800,3600;\nPD3200,3600;\nPU;\nSP0;\n"));
+        let gcode = plotter::gcode(&strokes, paper, 1500.0);
+        assert!(gcode.contains(
+            "G0 X20.000 Y20.000\nG1 Z0.000 F1500\nG1 X20.000 Y60.000 F1500\nG0 Z5.000\n"
+        ));
+        assert!(gcode.contains("M0 ; change to the red pen\n"));
+    }
+
+    #[test]
+    fn test_plotter_clips_to_canvas() {
+        let mut env = Environment::new();
+        let mut image = Image::new(100.0, 100.0);
+        run_on("ht circle 60 circle 500", &mut env, &mut image).unwrap();
+        let strokes = image.plot(plotter::Paper::parse("120x120").unwrap());
+        // the small circle sticks out on every side and is cut into four arcs, the big one is gone
+        assert_eq!(strokes.len(), 4);
+        for &(x, y) in strokes.iter().flat_map(|s| &s.points) {
+            assert!((10.0..=110.0).contains(&x) && (10.0..=110.0).contains(&y));
+        }
+        let gcode = plotter::gcode(&strokes, plotter::Paper::parse("120x120").unwrap(), 1500.0);
+        assert!(!gcode.contains('-'));
+    }
+
+    #[test]
+    fn test_animated_svg() {
+        let mut env = Environment::new();
+        let mut image = Image::new(100.0, 100.0);
+        image.animate_svg(50);
+        run_on("fd 10 wait 1 fd 10 label \"hi", &mut env, &mut image).unwrap();
+        let body = image.svg_body();
+        let lines: Vec<&str> = body.lines().collect();
+        assert!(lines[0].starts_with("<path d=\"M 50 50 L 50 40\" stroke"));
+        assert!(lines[0].ends_with("begin=\"0ms\" dur=\"50ms\" fill=\"freeze\" /></path>"));
+        assert!(lines[1].contains("begin=\"1050ms\" dur=\"50ms\""));
+        assert!(lines[2].starts_with(
+            "<g visibility=\"hidden\"><set attributeName=\"visibility\" to=\"visible\" \
+             begin=\"1100ms\" fill=\"freeze\" /><text"
+        ));
+        assert!(lines[3].contains("begin=\"1150ms\""));
+    }
 }
@@ -1,140 +1,263 @@
-use crate::{lexer::Token, parser::Expr};
-use std::collections::VecDeque;
+use crate::error::LogoError;
+use crate::parser::parse_quoted_name;
+use crate::{lexer, lexer::Token, lexer::Tokens, parser::Expr};
+use std::collections::HashMap;
 
-pub fn parse(tokens: &mut VecDeque<Token>) -> Box<Expr> {
-    parse_eq(tokens)
+pub fn parse(tokens: &mut Tokens, procs: &HashMap<String, usize>) -> Result<Box<Expr>, LogoError> {
+    parse_or(tokens, procs)
 }
 
-fn parse_eq(tokens: &mut VecDeque<Token>) -> Box<Expr> {
-    let mut left_operand = parse_addition(tokens);
+fn parse_or(tokens: &mut Tokens, procs: &HashMap<String, usize>) -> Result<Box<Expr>, LogoError> {
+    let mut left_operand = parse_and(tokens, procs)?;
+    while let Some(&Token::Or) = tokens.front() {
+        tokens.pop_front();
+        let right_operand = parse_and(tokens, procs)?;
+        left_operand = Box::new(Expr::Or(left_operand, right_operand));
+    }
+    Ok(left_operand)
+}
+
+fn parse_and(tokens: &mut Tokens, procs: &HashMap<String, usize>) -> Result<Box<Expr>, LogoError> {
+    let mut left_operand = parse_eq(tokens, procs)?;
+    while let Some(&Token::And) = tokens.front() {
+        tokens.pop_front();
+        let right_operand = parse_eq(tokens, procs)?;
+        left_operand = Box::new(Expr::And(left_operand, right_operand));
+    }
+    Ok(left_operand)
+}
+
+fn parse_eq(tokens: &mut Tokens, procs: &HashMap<String, usize>) -> Result<Box<Expr>, LogoError> {
+    let mut left_operand = parse_addition(tokens, procs)?;
     loop {
-        match tokens.front() {
-            Some(&Token::Lt) => {
-                tokens.pop_front();
-                let right_operand = parse_addition(tokens);
-                left_operand = Box::new(Expr::Lt(left_operand, right_operand));
-            }
-            Some(&Token::Eq) => {
-                tokens.pop_front();
-                let right_operand = parse_addition(tokens);
-                left_operand = Box::new(Expr::Eq(left_operand, right_operand));
-            }
+        let comparison = match tokens.front() {
+            Some(&Token::Lt) => Expr::Lt,
+            Some(&Token::Gt) => Expr::Gt,
+            Some(&Token::Le) => Expr::Le,
+            Some(&Token::Ge) => Expr::Ge,
+            Some(&Token::Eq) => Expr::Eq,
+            Some(&Token::Ne) => Expr::Ne,
             _ => break,
-        }
+        };
+        tokens.pop_front();
+        let right_operand = parse_addition(tokens, procs)?;
+        left_operand = Box::new(comparison(left_operand, right_operand));
     }
-    left_operand
+    Ok(left_operand)
 }
 
-fn parse_addition(tokens: &mut VecDeque<Token>) -> Box<Expr> {
-    let mut left_operand = parse_multiplication(tokens);
+fn parse_addition(
+    tokens: &mut Tokens,
+    procs: &HashMap<String, usize>,
+) -> Result<Box<Expr>, LogoError> {
+    let mut left_operand = parse_multiplication(tokens, procs)?;
     loop {
         match tokens.front() {
             Some(&Token::Add) => {
                 tokens.pop_front();
-                let right_operand = parse_multiplication(tokens);
+                let right_operand = parse_multiplication(tokens, procs)?;
                 left_operand = Box::new(Expr::Add(left_operand, right_operand));
             }
-            Some(&Token::Sub) => {
+            Some(&Token::Sub) if !is_negative_input(tokens) => {
                 tokens.pop_front();
-                let right_operand = parse_multiplication(tokens);
+                let right_operand = parse_multiplication(tokens, procs)?;
                 left_operand = Box::new(Expr::Sub(left_operand, right_operand));
             }
             _ => break,
         }
     }
 
-    left_operand
+    Ok(left_operand)
 }
 
-fn parse_multiplication(tokens: &mut VecDeque<Token>) -> Box<Expr> {
-    let mut left_operand = parse_operand(tokens);
+fn parse_multiplication(
+    tokens: &mut Tokens,
+    procs: &HashMap<String, usize>,
+) -> Result<Box<Expr>, LogoError> {
+    let mut left_operand = parse_operand(tokens, procs)?;
     loop {
         match tokens.front() {
             Some(&Token::Mul) => {
                 tokens.pop_front();
-                let right_operand = parse_operand(tokens);
+                let right_operand = parse_operand(tokens, procs)?;
                 left_operand = Box::new(Expr::Mul(left_operand, right_operand));
             }
             Some(&Token::Div) => {
                 tokens.pop_front();
-                let right_operand = parse_operand(tokens);
+                let right_operand = parse_operand(tokens, procs)?;
                 left_operand = Box::new(Expr::Div(left_operand, right_operand));
             }
             _ => break,
         }
     }
 
-    left_operand
+    Ok(left_operand)
+}
+
+/* Input of a reporter written before its inputs, like sqrt or a procedure: everything up to a
+comparison, so sqrt 4 + 5 is 3, not 1 = 2 is true and and 1 < 2 3 < 4 takes two comparisons */
+fn parse_input(
+    tokens: &mut Tokens,
+    procs: &HashMap<String, usize>,
+) -> Result<Box<Expr>, LogoError> {
+    parse_eq(tokens, procs)
 }
 
-fn parse_operand(tokens: &mut VecDeque<Token>) -> Box<Expr> {
-    match tokens.pop_front() {
-        Some(Token::Number(Some(num))) => Box::new(Expr::Number(num)),
-        Some(Token::Variable(name)) => Box::new(Expr::Variable(name)),
+fn parse_operand(
+    tokens: &mut Tokens,
+    procs: &HashMap<String, usize>,
+) -> Result<Box<Expr>, LogoError> {
+    let expr = match tokens.pop_front() {
+        Some(Token::Number(Some(num))) => Expr::Number(num),
+        Some(Token::Variable(name)) => Expr::Variable(name, tokens.last_span()),
         Some(Token::LParen) => {
-            let result = parse_addition(tokens);
-            assert!(tokens.pop_front() == Some(Token::RParen), "Missing ')'");
-            result
+            let result = parse(tokens, procs)?;
+            if tokens.pop_front() != Some(Token::RParen) {
+                return Err(LogoError::parse("missing ')'", tokens.last_span()));
+            }
+            return Ok(result);
         }
-        Some(Token::RParen) => panic!("Unexpected ')' without '('"),
-        Some(Token::Sub) => {
-            let right = parse_operand(tokens);
-            return Box::new(Expr::Minus(right));
+        Some(Token::RParen) => {
+            return Err(LogoError::parse(
+                "unexpected ')' without '('",
+                tokens.last_span(),
+            ))
         }
-        Some(Token::Random) => {
-            let right = parse_operand(tokens);
-            return Box::new(Expr::Rand(right));
+        Some(Token::Sub) => Expr::Minus(parse_operand(tokens, procs)?),
+        Some(Token::Random) => Expr::Rand(parse_input(tokens, procs)?),
+        Some(Token::LBracket) => parse_list(tokens)?,
+        Some(Token::Pick) => Expr::Pick(parse_input(tokens, procs)?),
+        Some(Token::List) => Expr::ListOf(parse_input(tokens, procs)?, parse_input(tokens, procs)?),
+        Some(Token::Sentence) => {
+            Expr::Sentence(parse_input(tokens, procs)?, parse_input(tokens, procs)?)
         }
-        Some(Token::Pick) => {
-            let from = parse_list(tokens);
-            Box::new(Expr::Pick(from))
+        Some(Token::Fput) => Expr::Fput(parse_input(tokens, procs)?, parse_input(tokens, procs)?),
+        Some(Token::Lput) => Expr::Lput(parse_input(tokens, procs)?, parse_input(tokens, procs)?),
+        Some(Token::Item) => Expr::Item(parse_input(tokens, procs)?, parse_input(tokens, procs)?),
+        Some(Token::MemberP) => {
+            Expr::MemberP(parse_input(tokens, procs)?, parse_input(tokens, procs)?)
         }
-        Some(Token::Red)    => Box::new(Expr::Color("red".to_string())),
-        Some(Token::Orange) => Box::new(Expr::Color("orange".to_string())),
-        Some(Token::Yellow) => Box::new(Expr::Color("yellow".to_string())),
-        Some(Token::Green)  => Box::new(Expr::Color("green".to_string())),
-        Some(Token::Blue)   => Box::new(Expr::Color("blue".to_string())),
-        Some(Token::Violet) => Box::new(Expr::Color("violet".to_string())),
-        Some(Token::Black)  => Box::new(Expr::Color("black".to_string())),
-        _ => panic!("Invalid expression"),
-    }
-}
-
-fn parse_list(tokens: &mut VecDeque<Token>) -> VecDeque<Expr> {
-    let mut exprs = VecDeque::new();
-    if let Some(Token::LBracket) = tokens.pop_front() {
-        while let Some(token) = tokens.front() {
-            match token {
-                Token::Red
-                | Token::Orange
-                | Token::Yellow
-                | Token::Green
-                | Token::Blue
-                | Token::Violet
-                | Token::Black
-                | Token::Number(_)
-                | Token::Variable(_)
-                | Token::LParen
-                | Token::RParen
-                | Token::Sub
-                | Token::Random
-                | Token::Pick
-                | Token::Mul
-                | Token::Div
-                | Token::Add
-                | Token::Eq
-                | Token::Lt => {
-                    let expr = parse(tokens);
-                    exprs.push_back(*expr);
+        Some(Token::EmptyP) => Expr::EmptyP(parse_input(tokens, procs)?),
+        Some(Token::Thing) => Expr::Thing(parse_quoted_name(tokens)?, tokens.last_span()),
+        Some(Token::QuotedWord(word)) => Expr::Word(word),
+        Some(Token::Word) => Expr::Concat(parse_input(tokens, procs)?, parse_input(tokens, procs)?),
+        Some(Token::EqualP) => Expr::Eq(parse_input(tokens, procs)?, parse_input(tokens, procs)?),
+        Some(Token::Math(f)) => Expr::Math(f, parse_input(tokens, procs)?),
+        Some(Token::Math2(f)) => {
+            Expr::Math2(f, parse_input(tokens, procs)?, parse_input(tokens, procs)?)
+        }
+        Some(Token::Pi) => Expr::Number(std::f32::consts::PI),
+        Some(Token::Pos) => Expr::Pos,
+        Some(Token::XCor) => Expr::XCor,
+        Some(Token::YCor) => Expr::YCor,
+        Some(Token::Heading) => Expr::Heading,
+        Some(Token::Towards) => Expr::Towards(parse_input(tokens, procs)?),
+        Some(Token::Distance) => Expr::Distance(parse_input(tokens, procs)?),
+        Some(Token::PenDownP) => Expr::PenDownP,
+        Some(Token::ShownP) => Expr::ShownP,
+        Some(Token::PenColor) => Expr::PenColor,
+        Some(Token::Who) => Expr::Who,
+        Some(Token::Turtles) => Expr::Turtles,
+        Some(Token::True) => Expr::Bool(true),
+        Some(Token::False) => Expr::Bool(false),
+        Some(Token::Not) => Expr::Not(parse_input(tokens, procs)?),
+        Some(Token::And) => Expr::And(parse_input(tokens, procs)?, parse_input(tokens, procs)?),
+        Some(Token::Or) => Expr::Or(parse_input(tokens, procs)?, parse_input(tokens, procs)?),
+        Some(Token::LessP) => Expr::Lt(parse_input(tokens, procs)?, parse_input(tokens, procs)?),
+        Some(Token::GreaterP) => Expr::Gt(parse_input(tokens, procs)?, parse_input(tokens, procs)?),
+        Some(Token::LessEqualP) => {
+            Expr::Le(parse_input(tokens, procs)?, parse_input(tokens, procs)?)
+        }
+        Some(Token::GreaterEqualP) => {
+            Expr::Ge(parse_input(tokens, procs)?, parse_input(tokens, procs)?)
+        }
+        Some(Token::NotEqualP) => {
+            Expr::Ne(parse_input(tokens, procs)?, parse_input(tokens, procs)?)
+        }
+        Some(Token::First) => Expr::First(parse_input(tokens, procs)?),
+        Some(Token::Last) => Expr::Last(parse_input(tokens, procs)?),
+        Some(Token::ButFirst) => Expr::ButFirst(parse_input(tokens, procs)?),
+        Some(Token::ButLast) => Expr::ButLast(parse_input(tokens, procs)?),
+        Some(Token::Count) => Expr::Count(parse_input(tokens, procs)?),
+        Some(Token::Function(name)) => match procs.get(&name) {
+            Some(&arity) => {
+                let span = tokens.last_span();
+                let mut args = vec![];
+                for _ in 0..arity {
+                    args.push(*parse_input(tokens, procs)?);
                 }
-                Token::RBracket => break,
-                _ => panic!("parse list invalid expr"),
+                Expr::FunctionCall(name, args, span)
             }
+            None => {
+                return Err(LogoError::parse(
+                    format!("I don't know how to {}", name),
+                    tokens.last_span(),
+                ))
+            }
+        },
+        Some(Token::Red)    => Expr::Color("red".to_string()),
+        Some(Token::Orange) => Expr::Color("orange".to_string()),
+        Some(Token::Yellow) => Expr::Color("yellow".to_string()),
+        Some(Token::Green)  => Expr::Color("green".to_string()),
+        Some(Token::Blue)   => Expr::Color("blue".to_string()),
+        Some(Token::Violet) => Expr::Color("violet".to_string()),
+        Some(Token::Black)  => Expr::Color("black".to_string()),
+        None => {
+            return Err(LogoError::parse(
+                "expected an expression, found end of input",
+                tokens.last_span(),
+            ))
         }
+        Some(Token::Unknown) => return Err(LogoError::Lex(tokens.last_span())),
+        Some(_) => return Err(LogoError::parse("invalid expression", tokens.last_span())),
+    };
+    Ok(Box::new(expr))
+}
+
+/* List literal, its contents are not evaluated: [red 10 [1 2]] */
+fn parse_list(tokens: &mut Tokens) -> Result<Expr, LogoError> {
+    let mut items = vec![];
+    loop {
+        let item = match tokens.pop_front() {
+            Some(Token::RBracket) => return Ok(Expr::List(items)),
+            Some(Token::LBracket) => parse_list(tokens)?,
+            Some(token) => parse_list_word(token, tokens),
+            None => {
+                return Err(LogoError::parse(
+                    "list is missing a closing ']'",
+                    tokens.last_span(),
+                ))
+            }
+        };
+        items.push(item);
+    }
+}
+
+/* A word in a list goes on until a space or a bracket, whatever it is made of: [Hello, World!] */
+fn parse_list_word(first: Token, tokens: &mut Tokens) -> Expr {
+    let mut word = tokens.last_text().to_string();
+    let mut parts = vec![first];
+    while tokens.next_is_glued()
+        && !matches!(tokens.front(), Some(Token::LBracket | Token::RBracket))
+    {
+        parts.extend(tokens.pop_front());
+        word.push_str(tokens.last_text());
     }
-    if let Some(Token::RBracket) = tokens.pop_front() {
-        exprs
-    } else {
-        panic!("expr list: no closing ']'")
+    match parts.as_slice() {
+        [Token::Number(Some(num))] => Expr::Number(*num),
+        // -5 written without a space is a negative number, not a word and a number
+        [Token::Sub, Token::Number(Some(num))] => Expr::Number(-num),
+        _ => Expr::Word(word),
+    }
+}
+
+/* A minus with a space before it and none after starts a negative number for the next input,
+it is not a subtraction: setxy 10 -40 */
+pub fn is_negative_input(tokens: &Tokens) -> bool {
+    match (tokens.peek(0), tokens.peek(1)) {
+        (Some((Token::Sub, sub)), Some((Token::Number(_), number))) => {
+            !tokens.next_is_glued() && lexer::glued(sub, number)
+        }
+        _ => false,
     }
 }
@@ -1,3 +1,5 @@
+use crate::error::{LogoError, Span};
+use crate::parser::MathFn;
 use logos::Logos;
 use std::collections::VecDeque;
 
@@ -14,8 +16,37 @@ pub enum Token {
     Div,
     #[token("<")]
     Lt,
+    #[token(">")]
+    Gt,
+    #[token("<=")]
+    Le,
+    #[token(">=")]
+    Ge,
     #[token("=")]
     Eq,
+    #[token("<>")]
+    Ne,
+    /* logic */
+    #[token("and")]
+    And,
+    #[token("or")]
+    Or,
+    #[token("not")]
+    Not,
+    #[token("true")]
+    True,
+    #[token("false")]
+    False,
+    #[token("lessp")]
+    LessP,
+    #[token("greaterp")]
+    GreaterP,
+    #[token("lessequalp")]
+    LessEqualP,
+    #[token("greaterequalp")]
+    GreaterEqualP,
+    #[token("notequalp")]
+    NotEqualP,
     #[token("(")]
     LParen,
     #[token(")")]
@@ -29,6 +60,8 @@ pub enum Token {
     Repeat,
     #[token("stop")]
     Stop,
+    #[regex(r"output|op")]
+    Output,
     #[token("if")]
     If,
     #[token("ifelse")]
@@ -40,12 +73,77 @@ pub enum Token {
     /* built in procedures */
     #[token("show")]
     Show,
+    #[token("print")]
+    Print,
+    #[token("type")]
+    Type,
     #[token("wait")]
     Wait,
     #[token("pick")]
     Pick, // pick [list] - take random elem from the list
     #[token("random")]
     Random,
+    /* math */
+    #[token("sqrt", |_| MathFn::Sqrt)]
+    #[token("sin", |_| MathFn::Sin)]
+    #[token("cos", |_| MathFn::Cos)]
+    #[token("tan", |_| MathFn::Tan)]
+    #[token("arctan", |_| MathFn::Arctan)]
+    #[token("exp", |_| MathFn::Exp)]
+    #[token("ln", |_| MathFn::Ln)]
+    #[token("log10", |_| MathFn::Log10)]
+    #[token("abs", |_| MathFn::Abs)]
+    #[token("int", |_| MathFn::Int)]
+    #[token("round", |_| MathFn::Round)]
+    Math(MathFn),
+    #[token("power", |_| MathFn::Power)]
+    #[token("remainder", |_| MathFn::Remainder)]
+    #[token("modulo", |_| MathFn::Modulo)]
+    #[token("quotient", |_| MathFn::Quotient)]
+    #[token("min", |_| MathFn::Min)]
+    #[token("max", |_| MathFn::Max)]
+    Math2(MathFn),
+    #[token("pi")]
+    Pi,
+    /* words */
+    #[token("word")]
+    Word,
+    #[token("first")]
+    First,
+    #[token("last")]
+    Last,
+    #[regex(r"bf|butfirst")]
+    ButFirst,
+    #[regex(r"bl|butlast")]
+    ButLast,
+    #[token("count")]
+    Count,
+    #[token("equalp")]
+    EqualP,
+    /* lists */
+    #[token("list")]
+    List,
+    #[regex(r"se|sentence")]
+    Sentence,
+    #[token("fput")]
+    Fput,
+    #[token("lput")]
+    Lput,
+    #[token("item")]
+    Item,
+    #[token("emptyp")]
+    EmptyP,
+    #[token("memberp")]
+    MemberP,
+    /* variables */
+    #[token("make")]
+    Make,
+    #[token("local")]
+    Local,
+    #[token("global")]
+    Global,
+    #[token("thing")]
+    Thing,
     /* colors: red orange yellow green blue violet */
     #[token("red")]
     Red,
@@ -65,7 +163,46 @@ pub enum Token {
     #[token("clearscreen")]
     Clearscreen,
     #[token("setcolor")]
+    #[token("setpencolor")]
+    #[token("setpc")]
     Setcolor,
+    #[token("setbackground")]
+    #[token("setbg")]
+    SetBackground,
+    #[token("setpalette")]
+    SetPalette,
+    #[token("arc")]
+    Arc,
+    #[token("arc2")]
+    Arc2,
+    #[token("circle")]
+    Circle,
+    #[token("circle2")]
+    Circle2,
+    #[token("ellipse")]
+    Ellipse,
+    #[token("label")]
+    Label,
+    #[token("setlabelheight")]
+    SetLabelHeight,
+    #[token("setlabelfont")]
+    SetLabelFont,
+    #[token("setcanvas")]
+    SetCanvas,
+    #[token("window")]
+    Window,
+    #[token("wrap")]
+    Wrap,
+    #[token("fence")]
+    Fence,
+    #[token("filled")]
+    Filled,
+    #[token("fill")]
+    Fill,
+    #[token("setfillcolor")]
+    SetFillColor,
+    #[token("setfillrule")]
+    SetFillRule,
     #[regex(r"fd|forward")]
     Forward,
     #[regex(r"bk|back|backward")]
@@ -74,6 +211,36 @@ pub enum Token {
     Left,
     #[regex(r"rt|right")]
     Right,
+    #[token("setx")]
+    SetX,
+    #[token("sety")]
+    SetY,
+    #[token("setxy")]
+    SetXY,
+    #[token("setpos")]
+    SetPos,
+    #[regex(r"seth|setheading")]
+    SetHeading,
+    #[token("home")]
+    Home,
+    #[token("pos")]
+    Pos,
+    #[token("xcor")]
+    XCor,
+    #[token("ycor")]
+    YCor,
+    #[token("heading")]
+    Heading,
+    #[token("towards")]
+    Towards,
+    #[token("distance")]
+    Distance,
+    #[token("pendownp")]
+    PenDownP,
+    #[token("shownp")]
+    ShownP,
+    #[token("pencolor")]
+    PenColor,
     #[regex(r"pu|penup")]
     PenUp,
     #[regex(r"pd|pendown")]
@@ -84,23 +251,164 @@ pub enum Token {
     HideTurtle,
     #[token("setturtle")]
     SetTurtle,
+    #[token("tell")]
+    Tell,
+    #[token("ask")]
+    Ask,
+    #[token("each")]
+    Each,
+    #[token("who")]
+    Who,
+    #[token("turtles")]
+    Turtles,
+    #[token("setshape")]
+    SetShape,
+    #[token("setpensize")]
+    #[token("setwidth")]
+    SetPenSize,
+    #[token("setpenpattern")]
+    SetPenPattern,
+    #[token("setlinecap")]
+    SetLineCap,
+    #[token("setlinejoin")]
+    SetLineJoin,
+    #[token("setpenopacity")]
+    SetPenOpacity,
     /* datatypes */
     #[regex(r"[0-9]+(?:\.[0-9]+)?", |lex| lex.slice().parse::<f32>().ok())]
     Number(Option<f32>),
-    #[regex(r":[a-z]+", |lex| lex.slice().to_string())]
+    // the characters of a quoted word, except operators so :n-1 is :n - 1
+    #[regex(r":[^ \t\n\f\r\[\]()+\-*/=<>]+", |lex| lex.slice().to_string())]
     Variable(String),
+    #[regex(r#""[^ \t\n\f\r\[\]()]*"#, |lex| lex.slice()[1..].to_string())]
+    QuotedWord(String),
     #[regex(r"[a-z]+", |lex| lex.slice().to_string())]
     Function(String),
     #[regex(r"[ \t\n\f]+", logos::skip)]
     Error,
+    // input that is not Logo, fine in a list literal and an error anywhere else
+    Unknown,
 }
 
-pub fn process(input: &str) -> VecDeque<Token> {
-    let processed = Token::lexer(input)
-        .map(|tok| match tok {
-            Ok(t) => t,
-            Err(()) => panic!("Syntax error"),
-        })
-        .collect::<VecDeque<Token>>();
-    processed
+/* Token stream consumed by the parser, remembers where every token came from */
+#[derive(Debug)]
+pub struct Tokens {
+    tokens: VecDeque<(Token, Span, String)>,
+    last: Span,
+    last_text: String,
+    eof: Span,
+}
+
+impl Tokens {
+    pub fn front(&self) -> Option<&Token> {
+        self.tokens.front().map(|(tok, _, _)| tok)
+    }
+
+    pub fn pop_front(&mut self) -> Option<Token> {
+        match self.tokens.pop_front() {
+            Some((tok, span, text)) => {
+                self.last = span;
+                self.last_text = text;
+                Some(tok)
+            }
+            None => {
+                self.last = self.eof;
+                self.last_text = String::new();
+                None
+            }
+        }
+    }
+
+    /* Give back the token that was just popped */
+    pub fn push_front(&mut self, tok: Token) {
+        self.tokens
+            .push_front((tok, self.last, std::mem::take(&mut self.last_text)));
+    }
+
+    /* Source text of the most recently popped token, list literals keep words as written */
+    pub fn last_text(&self) -> &str {
+        &self.last_text
+    }
+
+    /* Span of the most recently popped token (end of input if there was none) */
+    pub fn last_span(&self) -> Span {
+        self.last
+    }
+
+    /* Whether the next token follows the most recently popped one without a space */
+    pub fn next_is_glued(&self) -> bool {
+        self.tokens.front().is_some() && glued(self.last, self.next_span())
+    }
+
+    /* Token n places ahead and its span, 0 is the next one */
+    pub fn peek(&self, n: usize) -> Option<(&Token, Span)> {
+        self.tokens.get(n).map(|(tok, span, _)| (tok, *span))
+    }
+
+    /* Span of the next token (end of input if there is none) */
+    pub fn next_span(&self) -> Span {
+        self.tokens.front().map_or(self.eof, |(_, span, _)| *span)
+    }
+}
+
+/* Whether the second token starts right where the first one ends */
+pub fn glued(first: Span, second: Span) -> bool {
+    first.line == second.line && first.column + first.len == second.column
+}
+
+/* Translates byte ranges of the input into line and column numbers */
+struct Lines<'a> {
+    input: &'a str,
+    // byte offset where each line starts
+    starts: Vec<usize>,
+    first_line: usize,
+}
+impl<'a> Lines<'a> {
+    fn new(input: &'a str, first_line: usize) -> Self {
+        let starts = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Lines {
+            input,
+            starts,
+            first_line,
+        }
+    }
+
+    fn span(&self, range: std::ops::Range<usize>) -> Span {
+        // the last line starting at or before the range
+        let line = self.starts.partition_point(|&start| start <= range.start) - 1;
+        Span {
+            line: line + self.first_line,
+            column: self.input[self.starts[line]..range.start].chars().count() + 1,
+            len: self.input[range].chars().count(),
+        }
+    }
+}
+
+pub fn process(input: &str) -> Result<Tokens, LogoError> {
+    process_from_line(input, 1)
+}
+
+/* Tokens of input that continues the source at the given line, like the next line typed in an
+interactive session */
+pub fn process_from_line(input: &str, first_line: usize) -> Result<Tokens, LogoError> {
+    let lines = Lines::new(input, first_line);
+    let mut lexer = Token::lexer(input);
+    let mut tokens = VecDeque::new();
+    while let Some(tok) = lexer.next() {
+        let span = lines.span(lexer.span());
+        let tok = tok.unwrap_or(Token::Unknown);
+        tokens.push_back((tok, span, lexer.slice().to_string()));
+    }
+    let eof = Span {
+        len: 1,
+        ..lines.span(input.len()..input.len())
+    };
+    Ok(Tokens {
+        tokens,
+        last: eof,
+        last_text: String::new(),
+        eof,
+    })
 }
@@ -0,0 +1,12 @@
+/* The interpreter as a library: run Logo on an evaluator::Image, then save the drawing or go
+through the primitives of its scene */
+pub mod color;
+pub mod error;
+pub mod evaluator;
+pub mod expr_parser;
+pub mod lexer;
+pub mod parser;
+pub mod plotter;
+pub mod raster;
+pub mod scene;
+pub mod svg;
@@ -1,12 +1,30 @@
-mod evaluator;
-mod expr_parser;
-mod lexer;
-mod parser;
-use std::collections::{HashMap, VecDeque};
+use logo::{error, evaluator, lexer, parser, plotter};
+use std::collections::HashMap;
 
 use clap::{App, Arg};
 use std::fs::File;
 use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::{process, thread};
+
+/* Lex, parse and evaluate one piece of source starting at the given line, keeping procedures
+between calls */
+fn run(
+    source: &str,
+    first_line: usize,
+    procs: &mut HashMap<String, usize>,
+    functions: &mut HashMap<String, (Vec<String>, parser::Block)>,
+    env: &mut evaluator::Environment,
+    image: &mut evaluator::Image,
+    verbose: bool,
+) -> Result<(), error::LogoError> {
+    let mut tokens = lexer::process_from_line(source, first_line)?;
+    let ast: parser::Block = parser::parse_program(&mut tokens, procs)?;
+    if verbose {
+        println!("Parsed to:\n{:?}", ast);
+    }
+    evaluator::eval_program(ast, functions, env, image)
+}
 
 fn get_matches() -> clap::ArgMatches<'static> {
     App::new("Logo Interpreter")
@@ -26,12 +44,193 @@ fn get_matches() -> clap::ArgMatches<'static> {
                 .help("Save image to file, default = result.svg")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("width")
+                .long("width")
+                .value_name("PIXELS")
+                .help("Set canvas width, default = 700")
+                .takes_value(true)
+                .validator(positive_number),
+        )
+        .arg(
+            Arg::with_name("height")
+                .long("height")
+                .value_name("PIXELS")
+                .help("Set canvas height, default = 700")
+                .takes_value(true)
+                .validator(positive_number),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Set image format, default = taken from the output file name, or svg")
+                .takes_value(true)
+                .possible_values(&["svg", "png", "gif", "frames", "hpgl", "gcode"]),
+        )
+        .arg(
+            Arg::with_name("frame-every")
+                .long("frame-every")
+                .value_name("N")
+                .help("Animations take a frame every N turtle commands and at each wait, default = 10")
+                .takes_value(true)
+                .validator(positive_integer),
+        )
+        .arg(
+            Arg::with_name("frame-delay")
+                .long("frame-delay")
+                .value_name("MS")
+                .help("Show each animation frame for MS milliseconds, default = 100")
+                .takes_value(true)
+                .validator(positive_integer),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .value_name("FACTOR")
+                .help("Scale bitmap images, 2 gives twice as many pixels each way, default = 1")
+                .takes_value(true)
+                .validator(positive_number)
+                .conflicts_with("dpi"),
+        )
+        .arg(
+            Arg::with_name("dpi")
+                .long("dpi")
+                .value_name("DPI")
+                .help("Set bitmap resolution, the canvas size is taken to be at 96 dpi")
+                .takes_value(true)
+                .validator(positive_number),
+        )
+        .arg(
+            Arg::with_name("precision")
+                .long("precision")
+                .value_name("DIGITS")
+                .help("Round SVG coordinates to DIGITS decimal places, default = keep them all")
+                .takes_value(true)
+                .validator(whole_number),
+        )
+        .arg(
+            Arg::with_name("no-merge")
+                .long("no-merge")
+                .help("Write every line as its own SVG element instead of joining them, for debugging"),
+        )
+        .arg(
+            Arg::with_name("paper")
+                .long("paper")
+                .value_name("SIZE")
+                .help("Plotter paper: a3, a4, a5, letter or width x height in mm like 300x200, default = a4")
+                .takes_value(true)
+                .validator(paper_size),
+        )
+        .arg(
+            Arg::with_name("feed")
+                .long("feed")
+                .value_name("MM_PER_MIN")
+                .help("G-code drawing speed in millimetres per minute, default = 1000")
+                .takes_value(true)
+                .validator(positive_number),
+        )
+        .arg(
+            Arg::with_name("animate")
+                .long("animate")
+                .help("Save an SVG that replays the drawing when opened in a browser"),
+        )
+        .arg(
+            Arg::with_name("step")
+                .long("step")
+                .value_name("MS")
+                .help("Animated SVGs take MS milliseconds to draw each line or shape, default = 20")
+                .takes_value(true)
+                .validator(positive_integer),
+        )
+        .arg(
+            Arg::with_name("autofit")
+                .long("autofit")
+                .help("Fit the image to everything drawn, wherever the turtle went"),
+        )
         .get_matches()
 }
+
+fn whole_number(value: String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("expected a whole number, got {}", value)),
+    }
+}
+
+fn positive_integer(value: String) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(x) if x > 0 => Ok(()),
+        _ => Err(format!("expected a positive whole number, got {}", value)),
+    }
+}
+
+fn paper_size(value: String) -> Result<(), String> {
+    match plotter::Paper::parse(&value) {
+        Some(_) => Ok(()),
+        None => Err(format!(
+            "expected a paper size like a4 or 300x200, got {}",
+            value
+        )),
+    }
+}
+
+fn positive_number(value: String) -> Result<(), String> {
+    match value.parse::<f32>() {
+        Ok(x) if x > 0.0 => Ok(()),
+        _ => Err(format!("expected a positive number, got {}", value)),
+    }
+}
+
 fn main() {
+    let interpreter = thread::Builder::new()
+        .stack_size(evaluator::INTERPRETER_STACK)
+        .spawn(interpret)
+        .expect("Failed to start the interpreter");
+    if interpreter.join().is_err() {
+        process::exit(101);
+    }
+}
+
+fn interpret() {
     let matches: clap::ArgMatches<'_> = get_matches();
-    let mut image = evaluator::Image::new(700.0, 700.0);
-    let mut functions: HashMap<String, (Vec<String>, VecDeque<parser::Command>)> = HashMap::new();
+    let size = |name| matches.value_of(name).map_or(700.0, |v| v.parse().unwrap());
+    let mut image = evaluator::Image::new(size("width"), size("height"));
+    image.set_autofit(matches.is_present("autofit"));
+    image.set_merge_lines(!matches.is_present("no-merge"));
+    if let Some(digits) = matches.value_of("precision") {
+        image.set_precision(digits.parse().unwrap());
+    }
+    let output_file = matches.value_of("output").unwrap_or("output.svg");
+    let format = matches.value_of("format").unwrap_or_else(|| {
+        match Path::new(output_file).extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("png") => "png",
+            Some(ext) if ext.eq_ignore_ascii_case("gif") => "gif",
+            Some(ext) if ext.eq_ignore_ascii_case("hpgl") || ext.eq_ignore_ascii_case("plt") => {
+                "hpgl"
+            }
+            Some(ext) if ext.eq_ignore_ascii_case("gcode") || ext.eq_ignore_ascii_case("nc") => {
+                "gcode"
+            }
+            _ => "svg",
+        }
+    });
+    let number = |name, default| {
+        matches
+            .value_of(name)
+            .map_or(default, |v| v.parse().unwrap())
+    };
+    if format == "gif" || format == "frames" {
+        image.record_frames(
+            number("frame-every", 10) as usize,
+            number("frame-delay", 100),
+        );
+    } else if format == "svg" && matches.is_present("animate") {
+        image.animate_svg(number("step", 20));
+    }
+    let mut functions: HashMap<String, (Vec<String>, parser::Block)> = HashMap::new();
+    let mut procs: HashMap<String, usize> = HashMap::new();
+    let mut env = evaluator::Environment::new();
     if let Some(input_file) = matches.value_of("input") {
         /* Parse a script - Redirect input from file */
         let file = File::open(input_file).expect("Failed to open input file");
@@ -40,22 +239,25 @@ fn main() {
         if reader.read_to_string(&mut prog).is_err() {
             panic!("Can not read input file")
         }
-        
-        let mut tokens: VecDeque<lexer::Token> = lexer::process(prog.as_str());
-        // println!("Tokenized to:\n{:?}\n**********************************************************", tokens);
-        
-        let ast: VecDeque<parser::Command> = parser::parse(&mut tokens);
-        // println!("Parsed to:");
-        // for cmd in ast.clone(){
-        //     println!("{:?}", cmd);
-        // }
-        // println!("**********************************************************");
-        _ = evaluator::eval_all(ast, &mut functions, &HashMap::new(), &mut image);
 
-        
+        if let Err(e) = run(
+            &prog,
+            1,
+            &mut procs,
+            &mut functions,
+            &mut env,
+            &mut image,
+            false,
+        ) {
+            // a half-run script leaves a half-made drawing, so nothing is saved
+            eprintln!("{}", e.render(&prog));
+            process::exit(1);
+        }
     } else {
         /* Start interactive session */
         println!("Enter Logo command (or 'exit' to quit)");
+        // everything typed so far, errors in procedures point to the line they were typed on
+        let mut session = String::new();
         loop {
             print!(">>");
             io::stdout().flush().unwrap();
@@ -71,15 +273,50 @@ fn main() {
             if input.eq_ignore_ascii_case("exit") {
                 break;
             }
-            let mut tokens: VecDeque<lexer::Token> = lexer::process(input);
-            let ast: VecDeque<parser::Command> = parser::parse(&mut tokens);
-            println!("Parsed to:\n{:?}", ast);
-            _ = evaluator::eval_all(ast, &mut functions, &HashMap::new(), &mut image);
+            let line = session.lines().count() + 1;
+            session.push_str(input);
+            session.push('\n');
+            if let Err(e) = run(
+                input,
+                line,
+                &mut procs,
+                &mut functions,
+                &mut env,
+                &mut image,
+                true,
+            ) {
+                eprintln!("{}", e.render(&session));
+            }
         }
     }
-    if let Some(output_file) = matches.value_of("output") {
-        image.save_svg(&format!("{}", output_file)[..]);
-    } else {
-        image.save_svg("output.svg");
+    let scale = match (matches.value_of("scale"), matches.value_of("dpi")) {
+        (Some(scale), _) => scale.parse().unwrap(),
+        (None, Some(dpi)) => dpi.parse::<f32>().unwrap() / 96.0,
+        (None, None) => 1.0,
+    };
+    let paper = plotter::Paper::parse(matches.value_of("paper").unwrap_or("a4")).unwrap();
+    let feed_rate = matches
+        .value_of("feed")
+        .map_or(1000.0, |v| v.parse().unwrap());
+    let saved = match format {
+        "png" => image.save_png(output_file, scale),
+        "gif" => image.save_gif(output_file, scale),
+        "frames" => image.save_frames(output_file, scale),
+        "hpgl" => {
+            image.save_hpgl(output_file, paper);
+            Ok(())
+        }
+        "gcode" => {
+            image.save_gcode(output_file, paper, feed_rate);
+            Ok(())
+        }
+        _ => {
+            image.save_svg(output_file);
+            Ok(())
+        }
+    };
+    if let Err(e) = saved {
+        eprintln!("{}", e);
+        process::exit(1);
     }
 }
@@ -1,11 +1,12 @@
+use crate::error::{LogoError, Span};
 use crate::expr_parser;
-use crate::lexer::Token;
-use std::collections::VecDeque;
+use crate::lexer::{Token, Tokens};
+use std::collections::{HashMap, VecDeque};
 
 /* Expr evauates to some value that can be used by commands */
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
-    Variable(String),
+    Variable(String, Span),
     Number(f32),
     Minus(Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
@@ -13,12 +14,75 @@ pub enum Expr {
     Div(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
     Lt(Box<Expr>, Box<Expr>),
+    Gt(Box<Expr>, Box<Expr>),
+    Le(Box<Expr>, Box<Expr>),
+    Ge(Box<Expr>, Box<Expr>),
     Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+    Bool(bool),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Math(MathFn, Box<Expr>),
+    Math2(MathFn, Box<Expr>, Box<Expr>),
+    Pos,
+    XCor,
+    YCor,
+    Heading,
+    Towards(Box<Expr>),
+    Distance(Box<Expr>),
+    PenDownP,
+    ShownP,
+    PenColor,
+    Who,
+    Turtles,
     Rand(Box<Expr>),
     Color(String),
-    Pick(VecDeque<Expr>),
+    Pick(Box<Expr>),
+    FunctionCall(String, Vec<Expr>, Span),
+    Thing(String, Span),
+    Word(String),
+    Concat(Box<Expr>, Box<Expr>),
+    First(Box<Expr>),
+    Last(Box<Expr>),
+    ButFirst(Box<Expr>),
+    ButLast(Box<Expr>),
+    Count(Box<Expr>),
+    List(Vec<Expr>),
+    ListOf(Box<Expr>, Box<Expr>),
+    Sentence(Box<Expr>, Box<Expr>),
+    Fput(Box<Expr>, Box<Expr>),
+    Lput(Box<Expr>, Box<Expr>),
+    Item(Box<Expr>, Box<Expr>),
+    EmptyP(Box<Expr>),
+    MemberP(Box<Expr>, Box<Expr>),
 }
 
+/* Built-in math reporters, trigonometry works in degrees */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MathFn {
+    Sqrt,
+    Sin,
+    Cos,
+    Tan,
+    Arctan,
+    Exp,
+    Ln,
+    Log10,
+    Abs,
+    Int,
+    Round,
+    Power,
+    Remainder,
+    Modulo,
+    Quotient,
+    Min,
+    Max,
+}
+
+/* Commands in the order they run, each with the span of the word it starts with */
+pub type Block = VecDeque<(Command, Span)>;
+
 /* Command handles turtle graphisc, stdout, programm state */
 #[derive(Debug, PartialEq, Clone)]
 pub enum Command {
@@ -26,117 +90,350 @@ pub enum Command {
     Backward(Expr),
     Right(Expr),
     Left(Expr),
+    SetX(Expr),
+    SetY(Expr),
+    SetXY(Expr, Expr),
+    SetPos(Expr),
+    SetHeading(Expr),
+    Home,
     Show(Expr),
-    Repeat(Expr, VecDeque<Command>),
-    FunctionDeclaration(String, Vec<String>, VecDeque<Command>),
+    Print(Expr),
+    Type(Expr),
+    Repeat(Expr, Block),
+    FunctionDeclaration(String, Vec<String>, Block),
     FunctionCall(String, Vec<Expr>),
-    If(Expr, VecDeque<Command>),
-    IfElse(Expr, VecDeque<Command>, VecDeque<Command>),
+    If(Expr, Block),
+    IfElse(Expr, Block, Block),
     Clearscreen,
     Stop,
+    Output(Expr),
+    Make(String, Expr),
+    Local(String),
+    Global(String),
     Setcolor(Expr),
+    SetBackground(Expr),
+    SetPalette(Expr, Expr),
+    Arc(Expr, Expr),
+    Arc2(Expr, Expr),
+    Circle(Expr),
+    Circle2(Expr),
+    Ellipse(Expr, Expr),
+    Label(Expr),
+    SetLabelHeight(Expr),
+    SetLabelFont(Expr),
+    SetCanvas(Expr, Expr),
+    Window,
+    Wrap,
+    Fence,
+    Filled(Expr, Block),
+    Fill(Block),
+    SetFillColor(Expr),
+    SetFillRule(Expr),
     PenUp,
     PenDown,
     ShowTurtle,
     HideTurtle,
+    SetShape(Expr),
+    SetPenSize(Expr),
+    SetPenPattern(Expr),
+    SetLineCap(Expr),
+    SetLineJoin(Expr),
+    SetPenOpacity(Expr),
     SetTurtle(Expr),
+    Tell(Expr),
+    Ask(Expr, Block),
+    Each(Block),
     Wait(Expr),
     // List(Vec<Expr>),
 }
 
-pub fn parse(tokens: &mut VecDeque<Token>) -> VecDeque<Command> {
+/* procs holds the number of inputs of every procedure declared so far */
+pub fn parse(tokens: &mut Tokens, procs: &mut HashMap<String, usize>) -> Result<Block, LogoError> {
     let mut commands = VecDeque::new();
 
     while let Some(token) = tokens.pop_front() {
+        let span = tokens.last_span();
         match token {
             Token::If => {
-                let pred = parse_expr(tokens);
-                let body = parse_block_brackets(tokens);
-                commands.push_back(Command::If(pred, body));
+                let pred = parse_expr(tokens, procs)?;
+                let body = parse_block_brackets(tokens, procs)?;
+                commands.push_back((Command::If(pred, body), span));
             }
             Token::IfElse => {
-                let pred = parse_expr(tokens);
-                let if_body = parse_block_brackets(tokens);
-                let else_body = parse_block_brackets(tokens);
-                commands.push_back(Command::IfElse(pred, if_body, else_body));
+                let pred = parse_expr(tokens, procs)?;
+                let if_body = parse_block_brackets(tokens, procs)?;
+                let else_body = parse_block_brackets(tokens, procs)?;
+                commands.push_back((Command::IfElse(pred, if_body, else_body), span));
             }
             Token::Repeat => {
-                let iters = parse_expr(tokens);
-                let body = parse_block_brackets(tokens);
-                commands.push_back(Command::Repeat(iters, body));
+                let iters = parse_expr(tokens, procs)?;
+                let body = parse_block_brackets(tokens, procs)?;
+                commands.push_back((Command::Repeat(iters, body), span));
             }
 
             Token::To => {
-                let name = parse_name(tokens);
+                let name = parse_name(tokens)?;
                 let args = parse_args(tokens);
-                let body = parse_block_end(tokens);
-                commands.push_back(Command::FunctionDeclaration(name, args, body));
+                procs.insert(name.clone(), args.len());
+                let body = parse_block_end(tokens, procs)?;
+                commands.push_back((Command::FunctionDeclaration(name, args, body), span));
             }
 
             Token::Function(name) => {
-                let args = parse_expr_seq(tokens);
-                commands.push_back(Command::FunctionCall(name, args));
+                let args = match procs.get(&name) {
+                    Some(&arity) => (0..arity)
+                        .map(|_| parse_expr(tokens, procs))
+                        .collect::<Result<Vec<Expr>, LogoError>>()?,
+                    // not declared yet, take every expression that follows
+                    None => parse_expr_seq(tokens, procs)?,
+                };
+                commands.push_back((Command::FunctionCall(name, args), span));
             }
 
-            Token::Forward | Token::Backward | Token::Right | Token::Left | Token::Show => {
-                let expr = parse_expr(tokens);
-                commands.push_back(match token {
-                    Token::Forward => Command::Forward(expr),
-                    Token::Backward => Command::Backward(expr),
-                    Token::Right => Command::Right(expr),
-                    Token::Left => Command::Left(expr),
-                    Token::Show => Command::Show(expr),
-                    _ => unreachable!(),
-                });
+            Token::Forward
+            | Token::Backward
+            | Token::Right
+            | Token::Left
+            | Token::SetX
+            | Token::SetY
+            | Token::SetPos
+            | Token::SetHeading
+            | Token::Show
+            | Token::Print
+            | Token::Type => {
+                let expr = parse_expr(tokens, procs)?;
+                commands.push_back((
+                    match token {
+                        Token::Forward => Command::Forward(expr),
+                        Token::Backward => Command::Backward(expr),
+                        Token::Right => Command::Right(expr),
+                        Token::Left => Command::Left(expr),
+                        Token::SetX => Command::SetX(expr),
+                        Token::SetY => Command::SetY(expr),
+                        Token::SetPos => Command::SetPos(expr),
+                        Token::SetHeading => Command::SetHeading(expr),
+                        Token::Show => Command::Show(expr),
+                        Token::Print => Command::Print(expr),
+                        Token::Type => Command::Type(expr),
+                        _ => unreachable!(),
+                    },
+                    span,
+                ));
             }
             Token::RBracket | Token::End => {
                 tokens.push_front(token);
-                return commands;
-            }
-            Token::PenDown => commands.push_back(Command::PenDown),
-            Token::PenUp => commands.push_back(Command::PenUp),
-            Token::Clearscreen => commands.push_back(Command::Clearscreen),
-            Token::Stop => commands.push_back(Command::Stop),
-            Token::ShowTurtle => commands.push_back(Command::ShowTurtle),
-            Token::HideTurtle => commands.push_back(Command::HideTurtle),
+                return Ok(commands);
+            }
+            Token::SetXY => {
+                let x = parse_expr(tokens, procs)?;
+                let y = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetXY(x, y), span));
+            }
+            Token::Home => commands.push_back((Command::Home, span)),
+            Token::PenDown => commands.push_back((Command::PenDown, span)),
+            Token::PenUp => commands.push_back((Command::PenUp, span)),
+            Token::Clearscreen => commands.push_back((Command::Clearscreen, span)),
+            Token::Stop => commands.push_back((Command::Stop, span)),
+            Token::ShowTurtle => commands.push_back((Command::ShowTurtle, span)),
+            Token::HideTurtle => commands.push_back((Command::HideTurtle, span)),
             Token::Setcolor => {
-                let color = parse_expr(tokens);
-                commands.push_back(Command::Setcolor(color));
+                let color = parse_expr(tokens, procs)?;
+                commands.push_back((Command::Setcolor(color), span));
+            }
+            Token::SetBackground => {
+                let color = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetBackground(color), span));
+            }
+            Token::SetPalette => {
+                let index = parse_expr(tokens, procs)?;
+                let color = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetPalette(index, color), span));
+            }
+            Token::Arc => {
+                let angle = parse_expr(tokens, procs)?;
+                let radius = parse_expr(tokens, procs)?;
+                commands.push_back((Command::Arc(angle, radius), span));
+            }
+            Token::Arc2 => {
+                let angle = parse_expr(tokens, procs)?;
+                let radius = parse_expr(tokens, procs)?;
+                commands.push_back((Command::Arc2(angle, radius), span));
+            }
+            Token::Circle => {
+                let radius = parse_expr(tokens, procs)?;
+                commands.push_back((Command::Circle(radius), span));
+            }
+            Token::Circle2 => {
+                let radius = parse_expr(tokens, procs)?;
+                commands.push_back((Command::Circle2(radius), span));
+            }
+            Token::Ellipse => {
+                let crosswise = parse_expr(tokens, procs)?;
+                let along = parse_expr(tokens, procs)?;
+                commands.push_back((Command::Ellipse(crosswise, along), span));
+            }
+            Token::Label => {
+                let text = parse_expr(tokens, procs)?;
+                commands.push_back((Command::Label(text), span));
+            }
+            Token::SetLabelHeight => {
+                let height = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetLabelHeight(height), span));
+            }
+            Token::SetLabelFont => {
+                let font = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetLabelFont(font), span));
+            }
+            Token::SetCanvas => {
+                let width = parse_expr(tokens, procs)?;
+                let height = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetCanvas(width, height), span));
+            }
+            Token::Window => commands.push_back((Command::Window, span)),
+            Token::Wrap => commands.push_back((Command::Wrap, span)),
+            Token::Fence => commands.push_back((Command::Fence, span)),
+            Token::Filled => {
+                let color = parse_expr(tokens, procs)?;
+                let body = parse_block_brackets(tokens, procs)?;
+                commands.push_back((Command::Filled(color, body), span));
+            }
+            Token::Fill => {
+                let body = parse_block_brackets(tokens, procs)?;
+                commands.push_back((Command::Fill(body), span));
+            }
+            Token::SetFillColor => {
+                let color = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetFillColor(color), span));
+            }
+            Token::SetFillRule => {
+                let rule = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetFillRule(rule), span));
+            }
+            Token::SetShape => {
+                let shape = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetShape(shape), span));
+            }
+            Token::SetPenSize => {
+                let size = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetPenSize(size), span));
+            }
+            Token::SetPenPattern => {
+                let pattern = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetPenPattern(pattern), span));
+            }
+            Token::SetLineCap => {
+                let cap = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetLineCap(cap), span));
+            }
+            Token::SetLineJoin => {
+                let join = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetLineJoin(join), span));
+            }
+            Token::SetPenOpacity => {
+                let opacity = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetPenOpacity(opacity), span));
             }
             Token::SetTurtle => {
-                let num = parse_expr(tokens);
-                commands.push_back(Command::SetTurtle(num));
+                let num = parse_expr(tokens, procs)?;
+                commands.push_back((Command::SetTurtle(num), span));
+            }
+            Token::Tell => {
+                let ids = parse_expr(tokens, procs)?;
+                commands.push_back((Command::Tell(ids), span));
+            }
+            Token::Ask => {
+                let ids = parse_expr(tokens, procs)?;
+                let body = parse_block_brackets(tokens, procs)?;
+                commands.push_back((Command::Ask(ids, body), span));
+            }
+            Token::Each => {
+                let body = parse_block_brackets(tokens, procs)?;
+                commands.push_back((Command::Each(body), span));
             }
             Token::Wait => {
-                let num = parse_expr(tokens);
-                commands.push_back(Command::Wait(num));
+                let num = parse_expr(tokens, procs)?;
+                commands.push_back((Command::Wait(num), span));
+            }
+            Token::Output => {
+                let value = parse_expr(tokens, procs)?;
+                commands.push_back((Command::Output(value), span));
+            }
+            Token::Make => {
+                let name = parse_quoted_name(tokens)?;
+                let value = parse_expr(tokens, procs)?;
+                commands.push_back((Command::Make(name, value), span));
             }
+            Token::Local => commands.push_back((Command::Local(parse_quoted_name(tokens)?), span)),
+            Token::Global => {
+                commands.push_back((Command::Global(parse_quoted_name(tokens)?), span))
+            }
+            Token::Unknown => return Err(LogoError::Lex(span)),
             _ => {
-                // TODO
+                return Err(LogoError::parse("expected a command", tokens.last_span()));
             }
         }
     }
-    commands
+    Ok(commands)
 }
 
-fn parse_expr_seq(tokens: &mut VecDeque<Token>) -> Vec<Expr> {
-    let mut args: Vec<Expr> = vec![];
-    loop {
-        match tokens.front() {
-            Some(Token::Number(Some(_)))
-            | Some(Token::Variable(_))
-            | Some(Token::LParen)
-            | Some(Token::Random) => {
-                let expr = parse_expr(tokens);
-                args.push(expr);
-            }
-            _ => break, // _ => panic!("Parse expr seq: unexpected token {:?}", tokens.front()),
+/* Parse a whole program, a stray ']' or END is an error at the top level */
+pub fn parse_program(
+    tokens: &mut Tokens,
+    procs: &mut HashMap<String, usize>,
+) -> Result<Block, LogoError> {
+    declare_procedures(tokens, procs);
+    let commands = parse(tokens, procs)?;
+    match tokens.pop_front() {
+        None => Ok(commands),
+        Some(Token::End) => Err(LogoError::parse("END without TO", tokens.last_span())),
+        Some(_) => Err(LogoError::parse("unexpected ']'", tokens.last_span())),
+    }
+}
+
+/* Record the inputs of every procedure in the program before any of it is parsed, so calls above
+a TO know how many inputs to take and procedures can call each other */
+fn declare_procedures(tokens: &Tokens, procs: &mut HashMap<String, usize>) {
+    let mut i = 0;
+    while let Some((tok, _)) = tokens.peek(i) {
+        i += 1;
+        if let (Token::To, Some((Token::Function(name), _))) = (tok, tokens.peek(i)) {
+            let mut inputs = 0;
+            while let Some((Token::Variable(_), _)) = tokens.peek(i + 1 + inputs) {
+                inputs += 1;
+            }
+            procs.insert(name.clone(), inputs);
         }
     }
-    args
 }
 
-fn parse_args(tokens: &mut VecDeque<Token>) -> Vec<String> {
+fn parse_expr_seq(
+    tokens: &mut Tokens,
+    procs: &HashMap<String, usize>,
+) -> Result<Vec<Expr>, LogoError> {
+    let mut args: Vec<Expr> = vec![];
+    while matches!(
+        tokens.front(),
+        Some(
+            Token::Number(Some(_))
+                | Token::Variable(_)
+                | Token::QuotedWord(_)
+                | Token::True
+                | Token::False
+                | Token::Math(_)
+                | Token::Math2(_)
+                | Token::Pi
+                | Token::LBracket
+                | Token::LParen
+                | Token::Random
+        )
+    ) || expr_parser::is_negative_input(tokens)
+    {
+        args.push(parse_expr(tokens, procs)?);
+    }
+    Ok(args)
+}
+
+fn parse_args(tokens: &mut Tokens) -> Vec<String> {
     let mut args: Vec<String> = vec![];
     loop {
         let tok = tokens.pop_front();
@@ -156,37 +453,66 @@ fn parse_args(tokens: &mut VecDeque<Token>) -> Vec<String> {
     args
 }
 
-fn parse_name(tokens: &mut VecDeque<Token>) -> String {
+fn parse_name(tokens: &mut Tokens) -> Result<String, LogoError> {
+    match tokens.pop_front() {
+        Some(Token::Function(name)) => Ok(name),
+        _ => Err(LogoError::parse(
+            "expected procedure name after TO",
+            tokens.last_span(),
+        )),
+    }
+}
+
+/* Variable name given as a quoted word: make "size */
+pub fn parse_quoted_name(tokens: &mut Tokens) -> Result<String, LogoError> {
     match tokens.pop_front() {
-        Some(Token::Function(name)) => name,
-        _ => panic!("Expected function name after TO"),
+        Some(Token::QuotedWord(name)) => Ok(name),
+        _ => Err(LogoError::parse(
+            "expected a quoted variable name like \"size",
+            tokens.last_span(),
+        )),
     }
 }
 
-fn parse_block_end(tokens: &mut VecDeque<Token>) -> VecDeque<Command> {
-    let body = parse(tokens);
+fn parse_block_end(
+    tokens: &mut Tokens,
+    procs: &mut HashMap<String, usize>,
+) -> Result<Block, LogoError> {
+    let body = parse(tokens, procs)?;
     if let Some(Token::End) = tokens.pop_front() {
-        body
+        Ok(body)
     } else {
-        panic!("Repeat: block should end with END")
+        Err(LogoError::parse(
+            "procedure body should end with END",
+            tokens.last_span(),
+        ))
     }
 }
 
-fn parse_block_brackets(tokens: &mut VecDeque<Token>) -> VecDeque<Command> {
+fn parse_block_brackets(
+    tokens: &mut Tokens,
+    procs: &mut HashMap<String, usize>,
+) -> Result<Block, LogoError> {
     if let Some(Token::LBracket) = tokens.pop_front() {
-        let body = parse(tokens);
+        let body = parse(tokens, procs)?;
         if let Some(Token::RBracket) = tokens.pop_front() {
-            body
+            Ok(body)
         } else {
-            panic!("Repeat: block should end with a ']'")
+            Err(LogoError::parse(
+                "block should end with a ']'",
+                tokens.last_span(),
+            ))
         }
     } else {
-        panic!("Repeat: block should start with a '['")
+        Err(LogoError::parse(
+            "block should start with a '['",
+            tokens.last_span(),
+        ))
     }
 }
 
-fn parse_expr(tokens: &mut VecDeque<Token>) -> Expr {
-    *expr_parser::parse(tokens)
+fn parse_expr(tokens: &mut Tokens, procs: &HashMap<String, usize>) -> Result<Expr, LogoError> {
+    Ok(*expr_parser::parse(tokens, procs)?)
 }
 
 #[cfg(test)]
@@ -194,45 +520,63 @@ mod tests {
     use super::*;
     use crate::lexer::process;
 
-    fn vec_to_vecdeque(vec: Vec<Command>) -> VecDeque<Command> {
+    fn vec_to_vecdeque(vec: Vec<(Command, Span)>) -> Block {
         let mut deque = VecDeque::new();
         deque.extend(vec);
         deque
     }
 
+    /* Span on the first line */
+    fn at(column: usize, len: usize) -> Span {
+        Span {
+            line: 1,
+            column,
+            len,
+        }
+    }
+
     #[test]
     fn test_parser_1() {
         let input = "forward 3 right 30+60 backward 4.5 left 40+4*5 show 6+2*8+5*9";
-        let mut tokens = process(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
 
         let expected = vec![
-            Command::Forward(Expr::Number(3.0)),
-            Command::Right(Expr::Add(
-                Box::new(Expr::Number(30.0)),
-                Box::new(Expr::Number(60.0)),
-            )),
-            Command::Backward(Expr::Number(4.5)),
-            Command::Left(Expr::Add(
-                Box::new(Expr::Number(40.0)),
-                Box::new(Expr::Mul(
-                    Box::new(Expr::Number(4.0)),
-                    Box::new(Expr::Number(5.0)),
+            (Command::Forward(Expr::Number(3.0)), at(1, 7)),
+            (
+                Command::Right(Expr::Add(
+                    Box::new(Expr::Number(30.0)),
+                    Box::new(Expr::Number(60.0)),
                 )),
-            )),
-            Command::Show(Expr::Add(
-                Box::new(Expr::Add(
-                    Box::new(Expr::Number(6.0)),
+                at(11, 5),
+            ),
+            (Command::Backward(Expr::Number(4.5)), at(23, 8)),
+            (
+                Command::Left(Expr::Add(
+                    Box::new(Expr::Number(40.0)),
                     Box::new(Expr::Mul(
-                        Box::new(Expr::Number(2.0)),
-                        Box::new(Expr::Number(8.0)),
+                        Box::new(Expr::Number(4.0)),
+                        Box::new(Expr::Number(5.0)),
                     )),
                 )),
-                Box::new(Expr::Mul(
-                    Box::new(Expr::Number(5.0)),
-                    Box::new(Expr::Number(9.0)),
+                at(36, 4),
+            ),
+            (
+                Command::Show(Expr::Add(
+                    Box::new(Expr::Add(
+                        Box::new(Expr::Number(6.0)),
+                        Box::new(Expr::Mul(
+                            Box::new(Expr::Number(2.0)),
+                            Box::new(Expr::Number(8.0)),
+                        )),
+                    )),
+                    Box::new(Expr::Mul(
+                        Box::new(Expr::Number(5.0)),
+                        Box::new(Expr::Number(9.0)),
+                    )),
                 )),
-            )),
+                at(48, 4),
+            ),
         ];
 
         assert_eq!(ast, expected);
@@ -241,26 +585,41 @@ mod tests {
     #[test]
     fn test_parser_2() {
         let input = "repeat 2 [fd 50 rt 90 to square :size repeat 4 [fd :size rt 90] end] end";
-        let mut tokens = process(input);
-        let ast = parse(&mut tokens);
-
-        let expected = vec_to_vecdeque(vec![Command::Repeat(
-            Expr::Number(2.0),
-            vec_to_vecdeque(vec![
-                Command::Forward(Expr::Number(50.0)),
-                Command::Right(Expr::Number(90.0)),
-                Command::FunctionDeclaration(
-                    "square".to_string(),
-                    vec![":size".to_string()],
-                    vec_to_vecdeque(vec![Command::Repeat(
-                        Expr::Number(4.0),
-                        vec_to_vecdeque(vec![
-                            Command::Forward(Expr::Variable(":size".to_string())),
-                            Command::Right(Expr::Number(90.0)),
-                        ]),
-                    )]),
-                ),
-            ]),
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
+
+        let expected = vec_to_vecdeque(vec![(
+            Command::Repeat(
+                Expr::Number(2.0),
+                vec_to_vecdeque(vec![
+                    (Command::Forward(Expr::Number(50.0)), at(11, 2)),
+                    (Command::Right(Expr::Number(90.0)), at(17, 2)),
+                    (
+                        Command::FunctionDeclaration(
+                            "square".to_string(),
+                            vec![":size".to_string()],
+                            vec_to_vecdeque(vec![(
+                                Command::Repeat(
+                                    Expr::Number(4.0),
+                                    vec_to_vecdeque(vec![
+                                        (
+                                            Command::Forward(Expr::Variable(
+                                                ":size".to_string(),
+                                                at(52, 5),
+                                            )),
+                                            at(49, 2),
+                                        ),
+                                        (Command::Right(Expr::Number(90.0)), at(58, 2)),
+                                    ]),
+                                ),
+                                at(39, 6),
+                            )]),
+                        ),
+                        at(23, 2),
+                    ),
+                ]),
+            ),
+            at(1, 6),
         )]);
 
         assert_eq!(ast, expected);
@@ -269,22 +628,31 @@ mod tests {
     #[test]
     fn test_parser_3() {
         let input = "repeat 3 [to triangle fd 50 rt 120 end triangle]";
-        let mut tokens = process(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
 
-        let expected = vec_to_vecdeque(vec![Command::Repeat(
-            Expr::Number(3.0),
-            vec_to_vecdeque(vec![
-                Command::FunctionDeclaration(
-                    "triangle".to_string(),
-                    vec![],
-                    vec_to_vecdeque(vec![
-                        Command::Forward(Expr::Number(50.0)),
-                        Command::Right(Expr::Number(120.0)),
-                    ]),
-                ),
-                Command::FunctionCall("triangle".to_string(), vec![]),
-            ]),
+        let expected = vec_to_vecdeque(vec![(
+            Command::Repeat(
+                Expr::Number(3.0),
+                vec_to_vecdeque(vec![
+                    (
+                        Command::FunctionDeclaration(
+                            "triangle".to_string(),
+                            vec![],
+                            vec_to_vecdeque(vec![
+                                (Command::Forward(Expr::Number(50.0)), at(23, 2)),
+                                (Command::Right(Expr::Number(120.0)), at(29, 2)),
+                            ]),
+                        ),
+                        at(11, 2),
+                    ),
+                    (
+                        Command::FunctionCall("triangle".to_string(), vec![]),
+                        at(40, 8),
+                    ),
+                ]),
+            ),
+            at(1, 6),
         )]);
 
         assert_eq!(ast, expected);
@@ -293,22 +661,25 @@ mod tests {
     #[test]
     fn test_parser_4() {
         let input = "fd 2 * 3 + 4 / 2 - :size";
-        let mut tokens = process(input);
-        let ast = parse(&mut tokens);
-
-        let expected = vec_to_vecdeque(vec![Command::Forward(Expr::Sub(
-            Box::new(Expr::Add(
-                Box::new(Expr::Mul(
-                    Box::new(Expr::Number(2.0)),
-                    Box::new(Expr::Number(3.0)),
-                )),
-                Box::new(Expr::Div(
-                    Box::new(Expr::Number(4.0)),
-                    Box::new(Expr::Number(2.0)),
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
+
+        let expected = vec_to_vecdeque(vec![(
+            Command::Forward(Expr::Sub(
+                Box::new(Expr::Add(
+                    Box::new(Expr::Mul(
+                        Box::new(Expr::Number(2.0)),
+                        Box::new(Expr::Number(3.0)),
+                    )),
+                    Box::new(Expr::Div(
+                        Box::new(Expr::Number(4.0)),
+                        Box::new(Expr::Number(2.0)),
+                    )),
                 )),
+                Box::new(Expr::Variable(":size".to_string(), at(20, 5))),
             )),
-            Box::new(Expr::Variable(":size".to_string())),
-        ))]);
+            at(1, 2),
+        )]);
 
         assert_eq!(ast, expected);
     }
@@ -317,15 +688,18 @@ mod tests {
     fn test_parser_5() {
         // Test case with a repeat block containing multiple commands
         let input = "repeat 5 [fd 100 rt 144]";
-        let mut tokens = process(input);
-        let ast = parse(&mut tokens);
-
-        let expected = vec_to_vecdeque(vec![Command::Repeat(
-            Expr::Number(5.0),
-            vec_to_vecdeque(vec![
-                Command::Forward(Expr::Number(100.0)),
-                Command::Right(Expr::Number(144.0)),
-            ]),
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
+
+        let expected = vec_to_vecdeque(vec![(
+            Command::Repeat(
+                Expr::Number(5.0),
+                vec_to_vecdeque(vec![
+                    (Command::Forward(Expr::Number(100.0)), at(11, 2)),
+                    (Command::Right(Expr::Number(144.0)), at(18, 2)),
+                ]),
+            ),
+            at(1, 6),
         )]);
 
         assert_eq!(ast, expected);
@@ -335,19 +709,28 @@ mod tests {
     fn test_parser_6() {
         // Test case with a function declaration and a repeat block inside
         let input = "to star :len repeat 5 [fd :len rt 144] end";
-        let mut tokens = process(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
 
-        let expected = vec_to_vecdeque(vec![Command::FunctionDeclaration(
-            "star".to_string(),
-            vec![":len".to_string()],
-            vec_to_vecdeque(vec![Command::Repeat(
-                Expr::Number(5.0),
-                vec_to_vecdeque(vec![
-                    Command::Forward(Expr::Variable(":len".to_string())),
-                    Command::Right(Expr::Number(144.0)),
-                ]),
-            )]),
+        let expected = vec_to_vecdeque(vec![(
+            Command::FunctionDeclaration(
+                "star".to_string(),
+                vec![":len".to_string()],
+                vec_to_vecdeque(vec![(
+                    Command::Repeat(
+                        Expr::Number(5.0),
+                        vec_to_vecdeque(vec![
+                            (
+                                Command::Forward(Expr::Variable(":len".to_string(), at(27, 4))),
+                                at(24, 2),
+                            ),
+                            (Command::Right(Expr::Number(144.0)), at(32, 2)),
+                        ]),
+                    ),
+                    at(14, 6),
+                )]),
+            ),
+            at(1, 2),
         )]);
 
         assert_eq!(ast, expected);
@@ -358,38 +741,50 @@ mod tests {
         // Test case with a function declaration, fd command, repeat block, and function call
         let input =
             "to funkcyja :xd :xdd fd 20 rt 3+6+(4+6)*8 end fd 23 repeat 123 [lt 1] funkcyja 3 4";
-        let mut tokens = process(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
 
         let expected = vec_to_vecdeque(vec![
-            Command::FunctionDeclaration(
-                "funkcyja".to_string(),
-                vec![":xd".to_string(), ":xdd".to_string()],
-                vec_to_vecdeque(vec![
-                    Command::Forward(Expr::Number(20.0)),
-                    Command::Right(Expr::Add(
-                        Box::new(Expr::Add(
-                            Box::new(Expr::Number(3.0)),
-                            Box::new(Expr::Number(6.0)),
-                        )),
-                        Box::new(Expr::Mul(
-                            Box::new(Expr::Add(
-                                Box::new(Expr::Number(4.0)),
-                                Box::new(Expr::Number(6.0)),
+            (
+                Command::FunctionDeclaration(
+                    "funkcyja".to_string(),
+                    vec![":xd".to_string(), ":xdd".to_string()],
+                    vec_to_vecdeque(vec![
+                        (Command::Forward(Expr::Number(20.0)), at(22, 2)),
+                        (
+                            Command::Right(Expr::Add(
+                                Box::new(Expr::Add(
+                                    Box::new(Expr::Number(3.0)),
+                                    Box::new(Expr::Number(6.0)),
+                                )),
+                                Box::new(Expr::Mul(
+                                    Box::new(Expr::Add(
+                                        Box::new(Expr::Number(4.0)),
+                                        Box::new(Expr::Number(6.0)),
+                                    )),
+                                    Box::new(Expr::Number(8.0)),
+                                )),
                             )),
-                            Box::new(Expr::Number(8.0)),
-                        )),
-                    )),
-                ]),
+                            at(28, 2),
+                        ),
+                    ]),
+                ),
+                at(1, 2),
             ),
-            Command::Forward(Expr::Number(23.0)),
-            Command::Repeat(
-                Expr::Number(123.0),
-                vec_to_vecdeque(vec![Command::Left(Expr::Number(1.0))]),
+            (Command::Forward(Expr::Number(23.0)), at(47, 2)),
+            (
+                Command::Repeat(
+                    Expr::Number(123.0),
+                    vec_to_vecdeque(vec![(Command::Left(Expr::Number(1.0)), at(65, 2))]),
+                ),
+                at(53, 6),
             ),
-            Command::FunctionCall(
-                "funkcyja".to_string(),
-                vec![Expr::Number(3.0), Expr::Number(4.0)],
+            (
+                Command::FunctionCall(
+                    "funkcyja".to_string(),
+                    vec![Expr::Number(3.0), Expr::Number(4.0)],
+                ),
+                at(71, 8),
             ),
         ]);
 
@@ -400,64 +795,94 @@ mod tests {
     fn test_parser_8() {
         // Test case with a complex function declaration for a tree pattern
         let input = "to tree :size forward :size*0.333 left 30 tree :size*2*0.333 right 30 forward :size*0.666 right 25 tree :size*0.5 left 25 forward :size*0.333 right 25 tree :size*0.5 left 25 forward :size*0.666 back :size end tree 150";
-        let mut tokens = process(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
 
         let expected = vec_to_vecdeque(vec![
-            Command::FunctionDeclaration(
-                "tree".to_string(),
-                vec![":size".to_string()],
-                vec_to_vecdeque(vec![
-                    Command::Forward(Expr::Mul(
-                        Box::new(Expr::Variable(":size".to_string())),
-                        Box::new(Expr::Number(0.333)),
-                    )),
-                    Command::Left(Expr::Number(30.0)),
-                    Command::FunctionCall(
-                        "tree".to_string(),
-                        vec![Expr::Mul(
-                            Box::new(Expr::Mul(
-                                Box::new(Expr::Variable(":size".to_string())),
-                                Box::new(Expr::Number(2.0)),
+            (
+                Command::FunctionDeclaration(
+                    "tree".to_string(),
+                    vec![":size".to_string()],
+                    vec_to_vecdeque(vec![
+                        (
+                            Command::Forward(Expr::Mul(
+                                Box::new(Expr::Variable(":size".to_string(), at(23, 5))),
+                                Box::new(Expr::Number(0.333)),
                             )),
-                            Box::new(Expr::Number(0.333)),
-                        )],
-                    ),
-                    Command::Right(Expr::Number(30.0)),
-                    Command::Forward(Expr::Mul(
-                        Box::new(Expr::Variable(":size".to_string())),
-                        Box::new(Expr::Number(0.666)),
-                    )),
-                    Command::Right(Expr::Number(25.0)),
-                    Command::FunctionCall(
-                        "tree".to_string(),
-                        vec![Expr::Mul(
-                            Box::new(Expr::Variable(":size".to_string())),
-                            Box::new(Expr::Number(0.5)),
-                        )],
-                    ),
-                    Command::Left(Expr::Number(25.0)),
-                    Command::Forward(Expr::Mul(
-                        Box::new(Expr::Variable(":size".to_string())),
-                        Box::new(Expr::Number(0.333)),
-                    )),
-                    Command::Right(Expr::Number(25.0)),
-                    Command::FunctionCall(
-                        "tree".to_string(),
-                        vec![Expr::Mul(
-                            Box::new(Expr::Variable(":size".to_string())),
-                            Box::new(Expr::Number(0.5)),
-                        )],
-                    ),
-                    Command::Left(Expr::Number(25.0)),
-                    Command::Forward(Expr::Mul(
-                        Box::new(Expr::Variable(":size".to_string())),
-                        Box::new(Expr::Number(0.666)),
-                    )),
-                    Command::Backward(Expr::Variable(":size".to_string())),
-                ]),
+                            at(15, 7),
+                        ),
+                        (Command::Left(Expr::Number(30.0)), at(35, 4)),
+                        (
+                            Command::FunctionCall(
+                                "tree".to_string(),
+                                vec![Expr::Mul(
+                                    Box::new(Expr::Mul(
+                                        Box::new(Expr::Variable(":size".to_string(), at(48, 5))),
+                                        Box::new(Expr::Number(2.0)),
+                                    )),
+                                    Box::new(Expr::Number(0.333)),
+                                )],
+                            ),
+                            at(43, 4),
+                        ),
+                        (Command::Right(Expr::Number(30.0)), at(62, 5)),
+                        (
+                            Command::Forward(Expr::Mul(
+                                Box::new(Expr::Variable(":size".to_string(), at(79, 5))),
+                                Box::new(Expr::Number(0.666)),
+                            )),
+                            at(71, 7),
+                        ),
+                        (Command::Right(Expr::Number(25.0)), at(91, 5)),
+                        (
+                            Command::FunctionCall(
+                                "tree".to_string(),
+                                vec![Expr::Mul(
+                                    Box::new(Expr::Variable(":size".to_string(), at(105, 5))),
+                                    Box::new(Expr::Number(0.5)),
+                                )],
+                            ),
+                            at(100, 4),
+                        ),
+                        (Command::Left(Expr::Number(25.0)), at(115, 4)),
+                        (
+                            Command::Forward(Expr::Mul(
+                                Box::new(Expr::Variable(":size".to_string(), at(131, 5))),
+                                Box::new(Expr::Number(0.333)),
+                            )),
+                            at(123, 7),
+                        ),
+                        (Command::Right(Expr::Number(25.0)), at(143, 5)),
+                        (
+                            Command::FunctionCall(
+                                "tree".to_string(),
+                                vec![Expr::Mul(
+                                    Box::new(Expr::Variable(":size".to_string(), at(157, 5))),
+                                    Box::new(Expr::Number(0.5)),
+                                )],
+                            ),
+                            at(152, 4),
+                        ),
+                        (Command::Left(Expr::Number(25.0)), at(167, 4)),
+                        (
+                            Command::Forward(Expr::Mul(
+                                Box::new(Expr::Variable(":size".to_string(), at(183, 5))),
+                                Box::new(Expr::Number(0.666)),
+                            )),
+                            at(175, 7),
+                        ),
+                        (
+                            Command::Backward(Expr::Variable(":size".to_string(), at(200, 5))),
+                            at(195, 4),
+                        ),
+                    ]),
+                ),
+                at(1, 2),
+            ),
+            (
+                Command::FunctionCall("tree".to_string(), vec![Expr::Number(150.0)]),
+                at(210, 4),
             ),
-            Command::FunctionCall("tree".to_string(), vec![Expr::Number(150.0)]),
         ]);
 
         assert_eq!(ast, expected);
@@ -470,12 +895,15 @@ mod tests {
 
         // Test case with if statement
         let input = "if 4 [show 9]";
-        let mut tokens = process(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
 
-        let expected = vec_to_vecdeque(vec![If(
-            Number(4.0),
-            vec_to_vecdeque(vec![Show(Number(9.0))]),
+        let expected = vec_to_vecdeque(vec![(
+            If(
+                Number(4.0),
+                vec_to_vecdeque(vec![(Show(Number(9.0)), at(7, 4))]),
+            ),
+            at(1, 2),
         )]);
 
         assert_eq!(ast, expected);
@@ -487,17 +915,152 @@ mod tests {
         use Expr::*;
         // Test case with ifelse statement
         let input = "ifelse 3-3 [show 12] [show 2137]";
-        let mut tokens = process(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
+
+        let expected = vec_to_vecdeque(vec![(
+            IfElse(
+                Sub(Box::new(Number(3.0)), Box::new(Number(3.0))),
+                vec_to_vecdeque(vec![(Show(Number(12.0)), at(13, 4))]),
+                vec_to_vecdeque(vec![(Show(Number(2137.0)), at(23, 4))]),
+            ),
+            at(1, 6),
+        )]);
+
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn test_parser_output() {
+        use Expr::*;
+
+        let input = "to square :x output :x * :x end fd square 3 + 1 rt 90";
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
+
+        let expected = vec_to_vecdeque(vec![
+            (
+                Command::FunctionDeclaration(
+                    "square".to_string(),
+                    vec![":x".to_string()],
+                    vec_to_vecdeque(vec![(
+                        Command::Output(Mul(
+                            Box::new(Variable(":x".to_string(), at(21, 2))),
+                            Box::new(Variable(":x".to_string(), at(26, 2))),
+                        )),
+                        at(14, 6),
+                    )]),
+                ),
+                at(1, 2),
+            ),
+            (
+                Command::Forward(FunctionCall(
+                    "square".to_string(),
+                    vec![Add(Box::new(Number(3.0)), Box::new(Number(1.0)))],
+                    at(36, 6),
+                )),
+                at(33, 2),
+            ),
+            (Command::Right(Number(90.0)), at(49, 2)),
+        ]);
+
+        assert_eq!(ast, expected);
+
+        let mut tokens = process("fd cube 3").unwrap();
+        assert!(parse(&mut tokens, &mut HashMap::new()).is_err());
+    }
 
-        let expected = vec_to_vecdeque(vec![IfElse(
-            Sub(Box::new(Number(3.0)), Box::new(Number(3.0))),
-            vec_to_vecdeque(vec![Show(Number(12.0))]),
-            vec_to_vecdeque(vec![Show(Number(2137.0))]),
+    #[test]
+    fn test_parser_variables() {
+        use Expr::*;
+
+        let input = "make \"n 0 local \"step make \"n :n + thing \"step";
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
+
+        let expected = vec_to_vecdeque(vec![
+            (Command::Make("n".to_string(), Number(0.0)), at(1, 4)),
+            (Command::Local("step".to_string()), at(11, 5)),
+            (
+                Command::Make(
+                    "n".to_string(),
+                    Add(
+                        Box::new(Variable(":n".to_string(), at(31, 2))),
+                        Box::new(Thing("step".to_string(), at(42, 5))),
+                    ),
+                ),
+                at(23, 4),
+            ),
+        ]);
+
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn test_parser_list_literal() {
+        use Expr::*;
+
+        let input = "show fput -1 [red 10 [-2 :x] Hello, World! 10px] ";
+        let mut tokens = process(input).unwrap();
+        let ast = parse(&mut tokens, &mut HashMap::new()).unwrap();
+
+        let expected = vec_to_vecdeque(vec![(
+            Command::Show(Fput(
+                Box::new(Minus(Box::new(Number(1.0)))),
+                Box::new(List(vec![
+                    Word("red".to_string()),
+                    Number(10.0),
+                    List(vec![Number(-2.0), Word(":x".to_string())]),
+                    Word("Hello,".to_string()),
+                    Word("World!".to_string()),
+                    Word("10px".to_string()),
+                ])),
+            )),
+            at(1, 4),
         )]);
 
         assert_eq!(ast, expected);
     }
+
+    #[test]
+    fn test_parser_errors() {
+        use crate::error::{LogoError, Span};
+
+        // input that is not Logo is an error where it is used, it can only be part of a list
+        let mut tokens = process("fd 10\nrt $").unwrap();
+        let err = parse_program(&mut tokens, &mut HashMap::new()).unwrap_err();
+        assert_eq!(
+            err,
+            LogoError::Lex(Span {
+                line: 2,
+                column: 4,
+                len: 1
+            })
+        );
+        let mut tokens = process("fd 10\n$").unwrap();
+        assert!(matches!(
+            parse_program(&mut tokens, &mut HashMap::new()),
+            Err(LogoError::Lex(_))
+        ));
+
+        let mut tokens = process("repeat 4 [fd 10\n  rt (90 ]").unwrap();
+        let err = parse_program(&mut tokens, &mut HashMap::new()).unwrap_err();
+        assert_eq!(
+            err.span(),
+            Some(Span {
+                line: 2,
+                column: 10,
+                len: 1
+            })
+        );
+        assert_eq!(
+            err.render("repeat 4 [fd 10\n  rt (90 ]"),
+            "parse error at line 2, column 10: missing ')'\n   2 |   rt (90 ]\n     |          ^"
+        );
+
+        let mut tokens = process("fd 10 ] rt 5").unwrap();
+        assert!(parse_program(&mut tokens, &mut HashMap::new()).is_err());
+    }
 }
 
 /*
@@ -0,0 +1,265 @@
+/* Pen plotter output. The drawing is a list of strokes, each one drawn without lifting the pen */
+use crate::scene::{self, Point, Primitive};
+
+// how close two points must be for the pen to go on without lifting, in millimetres
+const JOIN_DISTANCE: f32 = 0.01;
+// the drawing is kept this far from the edges of the paper, in millimetres
+const PAPER_MARGIN: f32 = 10.0;
+// HPGL plotter units per millimetre
+const HPGL_UNITS: f32 = 40.0;
+// G-code pen heights in millimetres
+const PEN_UP_Z: f32 = 5.0;
+const PEN_DOWN_Z: f32 = 0.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stroke {
+    pub color: String,
+    pub points: Vec<Point>,
+}
+impl Stroke {
+    fn start(&self) -> Point {
+        self.points[0]
+    }
+    fn end(&self) -> Point {
+        self.points[self.points.len() - 1]
+    }
+}
+
+/* Paper size in millimetres */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Paper {
+    pub width: f32,
+    pub height: f32,
+}
+impl Paper {
+    /* A name like a4 or letter, or width x height in millimetres like 300x200 */
+    pub fn parse(name: &str) -> Option<Paper> {
+        let (width, height) = match name.to_lowercase().as_str() {
+            "a5" => (148.0, 210.0),
+            "a4" => (210.0, 297.0),
+            "a3" => (297.0, 420.0),
+            "letter" => (215.9, 279.4),
+            size => {
+                let (width, height) = size.split_once('x')?;
+                (width.parse().ok()?, height.parse().ok()?)
+            }
+        };
+        (width > 2.0 * PAPER_MARGIN && height > 2.0 * PAPER_MARGIN)
+            .then_some(Paper { width, height })
+    }
+}
+
+fn distance((x1, y1): Point, (x2, y2): Point) -> f32 {
+    (x2 - x1).hypot(y2 - y1)
+}
+
+/* What the pen draws, in the order it was drawn and in Logo coordinates. Arcs become short
+lines, fills, labels and turtles are left out */
+pub fn strokes<'a>(primitives: impl Iterator<Item = &'a Primitive>) -> Vec<Stroke> {
+    primitives
+        .filter_map(|primitive| {
+            let (points, pen) = match primitive {
+                Primitive::Polyline { points, pen } => (points.clone(), pen),
+                Primitive::Arc {
+                    centre,
+                    radius,
+                    start,
+                    angle,
+                    pen,
+                } => (scene::arc_points(*centre, *radius, *start, *angle), pen),
+                Primitive::Circle {
+                    centre,
+                    radius,
+                    pen,
+                } => (scene::arc_points(*centre, *radius, 0.0, 360.0), pen),
+                Primitive::Ellipse {
+                    centre,
+                    crosswise,
+                    along,
+                    heading,
+                    pen,
+                } => (
+                    scene::ellipse_points(*centre, *crosswise, *along, *heading),
+                    pen,
+                ),
+                _ => return None,
+            };
+            Some(Stroke {
+                color: pen.color.clone(),
+                points,
+            })
+        })
+        .collect()
+}
+
+/* The parts of the strokes inside the view box. A stroke that leaves it and comes back becomes
+two strokes, so the pen is lifted outside */
+fn clip(strokes: &[Stroke], view_box: (f32, f32, f32, f32)) -> Vec<Stroke> {
+    let (x, y, width, height) = view_box;
+    let bounds = (x, y, x + width, y + height);
+    let mut clipped = vec![];
+    let mut finish = |color: &String, points: Vec<Point>| {
+        if points.len() > 1 {
+            clipped.push(Stroke {
+                color: color.clone(),
+                points,
+            });
+        }
+    };
+    for stroke in strokes {
+        let mut points: Vec<Point> = vec![];
+        for line in stroke.points.windows(2) {
+            match scene::clip_line(line[0], line[1], bounds) {
+                Some((start, end)) => {
+                    if points.last() != Some(&start) {
+                        finish(&stroke.color, std::mem::take(&mut points));
+                        points.push(start);
+                    }
+                    points.push(end);
+                }
+                None => finish(&stroke.color, std::mem::take(&mut points)),
+            }
+        }
+        finish(&stroke.color, points);
+    }
+    clipped
+}
+
+/* Move the parts of strokes inside the view box onto the paper. The view box is the left, bottom,
+width and height of the saved part of the drawing in Logo coordinates. Paper coordinates are
+millimetres from the bottom left corner, the drawing is scaled to fit and centred */
+pub fn to_paper(strokes: &[Stroke], view_box: (f32, f32, f32, f32), paper: Paper) -> Vec<Stroke> {
+    let strokes = clip(strokes, view_box);
+    let (x, y, width, height) = view_box;
+    let scale = ((paper.width - 2.0 * PAPER_MARGIN) / width)
+        .min((paper.height - 2.0 * PAPER_MARGIN) / height);
+    let left = (paper.width - width * scale) / 2.0;
+    let bottom = (paper.height - height * scale) / 2.0;
+    strokes
+        .iter()
+        .map(|stroke| Stroke {
+            color: stroke.color.clone(),
+            points: stroke
+                .points
+                .iter()
+                .map(|&(px, py)| (left + (px - x) * scale, bottom + (py - y) * scale))
+                .collect(),
+        })
+        .collect()
+}
+
+/* Join strokes of the same color where one ends and the next begins */
+fn merge(strokes: Vec<Stroke>) -> Vec<Stroke> {
+    let mut merged: Vec<Stroke> = vec![];
+    for stroke in strokes {
+        match merged.last_mut() {
+            Some(last)
+                if last.color == stroke.color
+                    && distance(last.end(), stroke.start()) < JOIN_DISTANCE =>
+            {
+                last.points.extend_from_slice(&stroke.points[1..]);
+            }
+            _ => merged.push(stroke),
+        }
+    }
+    merged
+}
+
+/* Fewer and shorter pen-up moves: contiguous segments become one polyline, and strokes are drawn
+one color at a time, each time going to the nearest end of a stroke not drawn yet */
+pub fn optimise(strokes: Vec<Stroke>) -> Vec<Stroke> {
+    let mut colors: Vec<String> = vec![];
+    for stroke in &strokes {
+        if !colors.contains(&stroke.color) {
+            colors.push(stroke.color.clone());
+        }
+    }
+    let strokes = merge(strokes);
+    let mut ordered = Vec::with_capacity(strokes.len());
+    let mut position = (0.0, 0.0);
+    for color in colors {
+        let mut left: Vec<Stroke> = strokes
+            .iter()
+            .filter(|stroke| stroke.color == color)
+            .cloned()
+            .collect();
+        while !left.is_empty() {
+            let (index, reversed) = left
+                .iter()
+                .enumerate()
+                .flat_map(|(i, stroke)| {
+                    [
+                        (distance(position, stroke.start()), i, false),
+                        (distance(position, stroke.end()), i, true),
+                    ]
+                })
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, i, reversed)| (i, reversed))
+                .expect("there are strokes left");
+            let mut stroke = left.swap_remove(index);
+            if reversed {
+                stroke.points.reverse();
+            }
+            position = stroke.end();
+            ordered.push(stroke);
+        }
+    }
+    merge(ordered)
+}
+
+/* HPGL with one pen for each color, numbered in the order the colors are first used */
+pub fn hpgl(strokes: &[Stroke]) -> String {
+    let units = |(x, y): Point| {
+        format!(
+            "{},{}",
+            (x * HPGL_UNITS).round() as i32,
+            (y * HPGL_UNITS).round() as i32
+        )
+    };
+    let mut pens: Vec<&str> = vec![];
+    let mut out = String::from("IN;\n");
+    for stroke in strokes {
+        if pens.last() != Some(&stroke.color.as_str()) {
+            pens.push(&stroke.color);
+            out.push_str(&format!("SP{};\n", pens.len()));
+        }
+        out.push_str(&format!("PU{};\n", units(stroke.start())));
+        let rest: Vec<String> = stroke.points[1..].iter().map(|&p| units(p)).collect();
+        if rest.is_empty() {
+            out.push_str("PD;\n");
+        } else {
+            out.push_str(&format!("PD{};\n", rest.join(",")));
+        }
+    }
+    out.push_str("PU;\nSP0;\n");
+    out
+}
+
+/* G-code in millimetres, the pen goes up and down along Z. Drawing moves use the feed rate in
+millimetres per minute, the machine pauses so the pen can be changed when the color changes */
+pub fn gcode(strokes: &[Stroke], paper: Paper, feed_rate: f32) -> String {
+    let mm = |(x, y): Point| format!("X{:.3} Y{:.3}", x, y);
+    let mut out = format!(
+        "; Logo drawing on {} x {} mm paper\nG21\nG90\nG0 Z{:.3}\n",
+        paper.width, paper.height, PEN_UP_Z
+    );
+    let mut color: Option<&str> = None;
+    for stroke in strokes {
+        if color != Some(stroke.color.as_str()) {
+            if color.is_some() {
+                out.push_str(&format!("M0 ; change to the {} pen\n", stroke.color));
+            } else {
+                out.push_str(&format!("; {} pen\n", stroke.color));
+            }
+            color = Some(&stroke.color);
+        }
+        out.push_str(&format!("G0 {}\n", mm(stroke.start())));
+        out.push_str(&format!("G1 Z{:.3} F{}\n", PEN_DOWN_Z, feed_rate));
+        for &point in &stroke.points[1..] {
+            out.push_str(&format!("G1 {} F{}\n", mm(point), feed_rate));
+        }
+        out.push_str(&format!("G0 Z{:.3}\n", PEN_UP_Z));
+    }
+    out.push_str("G0 X0 Y0\nM2\n");
+    out
+}
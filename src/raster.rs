@@ -0,0 +1,110 @@
+use resvg::{tiny_skia, usvg};
+use std::fs::File;
+use std::sync::{Arc, OnceLock};
+
+/* System fonts for labels, loaded once as it is slow */
+fn fonts() -> Arc<usvg::fontdb::Database> {
+    static FONTS: OnceLock<Arc<usvg::fontdb::Database>> = OnceLock::new();
+    FONTS
+        .get_or_init(|| {
+            let mut fonts = usvg::fontdb::Database::new();
+            fonts.load_system_fonts();
+            // generic names default to Windows fonts, use what this system has instead
+            let installed = |names: &[&str]| {
+                names
+                    .iter()
+                    .find(|name| {
+                        fonts
+                            .faces()
+                            .any(|face| face.families.iter().any(|(family, _)| family == *name))
+                    })
+                    .map(|name| name.to_string())
+            };
+            let sans = installed(&["Arial", "DejaVu Sans", "Liberation Sans"]);
+            let serif = installed(&["Times New Roman", "DejaVu Serif", "Liberation Serif"]);
+            let mono = installed(&["Courier New", "DejaVu Sans Mono", "Liberation Mono"]);
+            if let Some(family) = sans {
+                fonts.set_sans_serif_family(family);
+            }
+            if let Some(family) = serif {
+                fonts.set_serif_family(family);
+            }
+            if let Some(family) = mono {
+                fonts.set_monospace_family(family);
+            }
+            Arc::new(fonts)
+        })
+        .clone()
+}
+
+/* Bitmaps can be at most this many pixels each way, bigger ones take gigabytes of memory */
+const MAX_BITMAP_SIZE: u32 = 16384;
+
+/* Length in whole pixels of a bitmap made at the given scale */
+pub fn pixels(length: f32, scale: f32) -> u32 {
+    (length * scale).ceil().max(1.0) as u32
+}
+
+/* GIF sizes are 16 bit numbers, bigger images can't be saved */
+pub fn gif_size(width: u32, height: u32) -> Result<(u16, u16), String> {
+    match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(format!(
+            "GIF images can be at most {} x {} pixels, this one would be {} x {}",
+            u16::MAX,
+            u16::MAX,
+            width,
+            height
+        )),
+    }
+}
+
+/* Rasterise an SVG document onto a white background, scale 2 gives an image twice as big */
+pub fn render(svg: &str, scale: f32) -> Result<tiny_skia::Pixmap, String> {
+    let options = usvg::Options {
+        fontdb: fonts(),
+        ..usvg::Options::default()
+    };
+    let tree = usvg::Tree::from_str(svg, &options).expect("the drawing is valid SVG");
+    let size = tree.size();
+    let (width, height) = (pixels(size.width(), scale), pixels(size.height(), scale));
+    if width > MAX_BITMAP_SIZE || height > MAX_BITMAP_SIZE {
+        return Err(format!(
+            "bitmap images can be at most {} x {} pixels, this one would be {} x {}",
+            MAX_BITMAP_SIZE, MAX_BITMAP_SIZE, width, height
+        ));
+    }
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).expect("image size is not zero");
+    pixmap.fill(tiny_skia::Color::WHITE);
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    Ok(pixmap)
+}
+
+/* Animated GIF playing the frames in a loop, each shown for its delay in milliseconds */
+pub fn save_gif(frames: &[(tiny_skia::Pixmap, u32)], filename: &str) -> Result<(), String> {
+    let (width, height) = match frames.first() {
+        Some((pixmap, _)) => gif_size(pixmap.width(), pixmap.height())?,
+        None => return Ok(()),
+    };
+    let file = File::create(filename).expect("Unable to create GIF file");
+    let mut encoder =
+        gif::Encoder::new(file, width, height, &[]).expect("Unable to write GIF file");
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .expect("Unable to write GIF file");
+    for (pixmap, delay) in frames {
+        // frames are opaque, so premultiplied colors are the plain ones
+        let mut rgba = pixmap.data().to_vec();
+        let mut frame = gif::Frame::from_rgba_speed(width, height, &mut rgba, 10);
+        // GIF delays are in hundredths of a second
+        frame.delay = (delay / 10).min(u16::MAX as u32) as u16;
+        encoder
+            .write_frame(&frame)
+            .expect("Unable to write GIF file");
+    }
+    Ok(())
+}
@@ -0,0 +1,208 @@
+/* The drawing kept as primitives, so it can be saved as SVG, PNG or for a plotter. Positions are in
+Logo coordinates: (0, 0) is the centre of the canvas, y points up and heading 0 is north */
+
+pub type Point = (f32, f32);
+
+// arcs and circles given as points have this many straight lines for a full turn
+const STEPS_PER_TURN: usize = 72;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TurtleShape {
+    Circle,
+    Triangle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineCap {
+    Butt,
+    Round,
+    Square,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineJoin {
+    Miter,
+    Round,
+    Bevel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FillRule {
+    NonZero,
+    EvenOdd,
+}
+
+/* How lines are drawn */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pen {
+    pub color: String,
+    pub width: f32,
+    // lengths of dashes and gaps, empty for a solid line
+    pub pattern: Vec<f32>,
+    pub cap: LineCap,
+    pub join: LineJoin,
+    pub opacity: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Primitive {
+    // lines drawn one after another without lifting the pen
+    Polyline {
+        points: Vec<Point>,
+        pen: Pen,
+    },
+    // part of a circle from the start heading, clockwise for a positive angle
+    Arc {
+        centre: Point,
+        radius: f32,
+        start: f32,
+        angle: f32,
+        pen: Pen,
+    },
+    Circle {
+        centre: Point,
+        radius: f32,
+        pen: Pen,
+    },
+    // radii across the heading and along it
+    Ellipse {
+        centre: Point,
+        crosswise: f32,
+        along: f32,
+        heading: f32,
+        pen: Pen,
+    },
+    // closed shapes filled together, so holes follow the fill rule
+    Fill {
+        parts: Vec<Vec<Point>>,
+        color: String,
+        rule: FillRule,
+    },
+    // text starting at the position, turned with the heading
+    Label {
+        position: Point,
+        heading: f32,
+        text: String,
+        font: String,
+        height: f32,
+        color: String,
+        opacity: f32,
+    },
+    Turtle {
+        position: Point,
+        heading: f32,
+        shape: TurtleShape,
+        size: f32,
+        pen: Pen,
+        fill: String,
+    },
+}
+
+/* Everything drawn so far, the first primitive at the bottom */
+#[derive(Debug, Clone, Default)]
+pub struct Scene {
+    background: Option<String>,
+    primitives: Vec<Primitive>,
+}
+
+impl Scene {
+    pub fn primitives(&self) -> impl Iterator<Item = &Primitive> {
+        self.primitives.iter()
+    }
+
+    pub fn background(&self) -> Option<&str> {
+        self.background.as_deref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.primitives.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+
+    pub(crate) fn set_background(&mut self, color: String) {
+        self.background = Some(color);
+    }
+
+    /* Put a primitive at a place in the stack, the end is on top of everything */
+    pub(crate) fn insert(&mut self, index: usize, primitive: Primitive) {
+        self.primitives.insert(index, primitive);
+    }
+
+    pub(crate) fn last_mut(&mut self) -> Option<&mut Primitive> {
+        self.primitives.last_mut()
+    }
+
+    /* The background stays, like the turtles */
+    pub(crate) fn clear(&mut self) {
+        self.primitives.clear();
+    }
+}
+
+/* Point at the given heading and distance from (x, y) */
+pub fn point_at(x: f32, y: f32, heading: f32, dist: f32) -> Point {
+    let rad = (heading as f64).rem_euclid(360.0).to_radians();
+    let dist = dist as f64;
+    (
+        (x as f64 + dist * rad.sin()) as f32,
+        (y as f64 + dist * rad.cos()) as f32,
+    )
+}
+
+/* Points along an arc, as many as a full circle would have in proportion */
+pub fn arc_points(centre: Point, radius: f32, start: f32, angle: f32) -> Vec<Point> {
+    let steps = ((angle.abs() / 360.0 * STEPS_PER_TURN as f32).ceil() as usize).max(1);
+    (0..=steps)
+        .map(|i| {
+            let heading = start + angle * i as f32 / steps as f32;
+            point_at(centre.0, centre.1, heading, radius)
+        })
+        .collect()
+}
+
+/* Points around an ellipse, the radius along the heading is the second one */
+pub fn ellipse_points(centre: Point, crosswise: f32, along: f32, heading: f32) -> Vec<Point> {
+    (0..=STEPS_PER_TURN)
+        .map(|i| {
+            let angle = (360.0 * i as f32 / STEPS_PER_TURN as f32).to_radians();
+            let (ax, ay) = point_at(centre.0, centre.1, heading, along * angle.cos());
+            point_at(ax, ay, heading + 90.0, crosswise * angle.sin())
+        })
+        .collect()
+}
+
+/* The part of the line from a to b inside the box given by its left, bottom, right and top edges,
+None if it is all outside. An end that is inside stays exactly where it was */
+pub fn clip_line(a: Point, b: Point, bounds: (f32, f32, f32, f32)) -> Option<(Point, Point)> {
+    let (left, bottom, right, top) = bounds;
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    for (p, q) in [
+        (-dx, a.0 - left),
+        (dx, right - a.0),
+        (-dy, a.1 - bottom),
+        (dy, top - a.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t = q / p;
+        if p < 0.0 {
+            t0 = t0.max(t);
+        } else {
+            t1 = t1.min(t);
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    let at = |t: f32| (a.0 + t * dx, a.1 + t * dy);
+    let start = if t0 == 0.0 { a } else { at(t0) };
+    let end = if t1 == 1.0 { b } else { at(t1) };
+    Some((start, end))
+}
@@ -0,0 +1,525 @@
+/* SVG export of a scene. Logo coordinates become SVG pixels, with (0, 0) at the top left corner
+of the canvas and y pointing down */
+use crate::scene::{self, FillRule, LineCap, LineJoin, Pen, Point, Primitive, Scene, TurtleShape};
+
+// without it browsers don't take a standalone file for SVG
+const NAMESPACE: &str = "http://www.w3.org/2000/svg";
+
+/* How the SVG is written */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    // decimal places kept in coordinates, all of them if not set
+    pub precision: Option<usize>,
+    // write polylines as paths, or every line as its own element
+    pub merge_lines: bool,
+}
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            precision: None,
+            merge_lines: true,
+        }
+    }
+}
+
+/* When a primitive is drawn in an animated SVG, in milliseconds from the start */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Timing {
+    begin_ms: u32,
+    dur_ms: u32,
+}
+
+/* Time line of an SVG that replays the drawing, with the timing of every primitive of the scene
+in the same order */
+#[derive(Debug, Clone)]
+pub(crate) struct Animation {
+    // how long drawing one line or element takes
+    step_ms: u32,
+    // when the next element starts to be drawn
+    time_ms: u32,
+    timings: Vec<Timing>,
+}
+impl Animation {
+    /* The primitives already in the scene are there from the start */
+    pub(crate) fn new(step_ms: u32, drawn: usize) -> Self {
+        Animation {
+            step_ms,
+            time_ms: 0,
+            timings: vec![Timing::default(); drawn],
+        }
+    }
+
+    /* A primitive drawn now, put at this place in the scene. It takes one step */
+    pub(crate) fn insert(&mut self, index: usize) {
+        let timing = Timing {
+            begin_ms: self.time_ms,
+            dur_ms: self.step_ms,
+        };
+        self.timings.insert(index, timing);
+        self.time_ms += self.step_ms;
+    }
+
+    /* The last primitive goes on for one more step, like a polyline getting another line */
+    pub(crate) fn extend_last(&mut self) {
+        if let Some(timing) = self.timings.last_mut() {
+            timing.dur_ms += self.step_ms;
+        }
+        self.time_ms += self.step_ms;
+    }
+
+    /* Nothing is drawn for a while */
+    pub(crate) fn wait(&mut self, ms: u32) {
+        self.time_ms += ms;
+    }
+
+    /* The scene was cleared, the clock goes on */
+    pub(crate) fn clear(&mut self) {
+        self.timings.clear();
+    }
+}
+
+/* SVG stroke attributes of the pen, the ones left at SVG defaults are omitted */
+fn stroke_attrs(pen: &Pen) -> String {
+    let mut attrs = format!("stroke=\"{}\" stroke-width=\"{}\"", pen.color, pen.width);
+    if !pen.pattern.is_empty() {
+        let dashes: Vec<String> = pen.pattern.iter().map(|d| d.to_string()).collect();
+        attrs.push_str(&format!(" stroke-dasharray=\"{}\"", dashes.join(" ")));
+    }
+    match pen.cap {
+        LineCap::Butt => {}
+        LineCap::Round => attrs.push_str(" stroke-linecap=\"round\""),
+        LineCap::Square => attrs.push_str(" stroke-linecap=\"square\""),
+    }
+    match pen.join {
+        LineJoin::Miter => {}
+        LineJoin::Round => attrs.push_str(" stroke-linejoin=\"round\""),
+        LineJoin::Bevel => attrs.push_str(" stroke-linejoin=\"bevel\""),
+    }
+    if pen.opacity < 1.0 {
+        attrs.push_str(&format!(" stroke-opacity=\"{}\"", pen.opacity));
+    }
+    attrs
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn show_at(time_ms: u32) -> String {
+    format!(
+        "<set attributeName=\"visibility\" to=\"visible\" begin=\"{}ms\" fill=\"freeze\" />",
+        time_ms
+    )
+}
+
+/* An SVG element before it is written out */
+struct Element {
+    name: &'static str,
+    attrs: String,
+    // the text of a label
+    text: Option<String>,
+}
+impl Element {
+    fn new(name: &'static str, attrs: String) -> Self {
+        Element {
+            name,
+            attrs,
+            text: None,
+        }
+    }
+
+    fn write(&self) -> String {
+        match &self.text {
+            Some(text) => self.write_with("", text),
+            None => format!("<{} {} />\n", self.name, self.attrs),
+        }
+    }
+
+    /* The element with more attributes and something inside it */
+    fn write_with(&self, attrs: &str, inside: &str) -> String {
+        format!(
+            "<{0} {1}{2}>{3}</{0}>\n",
+            self.name, self.attrs, attrs, inside
+        )
+    }
+}
+
+/* Writes scenes drawn on a canvas of the given size */
+pub struct Svg<'a> {
+    width: f32,
+    height: f32,
+    options: Options,
+    // the drawing is replayed when this is set
+    animation: Option<&'a Animation>,
+}
+impl<'a> Svg<'a> {
+    pub fn new(width: f32, height: f32, options: Options) -> Self {
+        Svg {
+            width,
+            height,
+            options,
+            animation: None,
+        }
+    }
+
+    /* Replay the drawing along the time line */
+    pub(crate) fn animated(self, animation: &'a Animation) -> Self {
+        Svg {
+            animation: Some(animation),
+            ..self
+        }
+    }
+
+    /* Logo coordinates to SVG pixels */
+    fn pixels(&self, (x, y): Point) -> (f32, f32) {
+        (self.width / 2.0 + x, self.height / 2.0 - y)
+    }
+
+    /* A coordinate rounded to the chosen precision, without trailing zeros */
+    fn num(&self, value: f32) -> String {
+        let Some(digits) = self.options.precision else {
+            return value.to_string();
+        };
+        let text = format!("{:.*}", digits, value);
+        let text = match text.contains('.') {
+            true => text.trim_end_matches('0').trim_end_matches('.'),
+            false => &text,
+        };
+        match text {
+            "-0" => "0".to_string(),
+            _ => text.to_string(),
+        }
+    }
+
+    /* SVG element of a primitive */
+    pub fn element(&self, primitive: &Primitive) -> String {
+        self.tag(primitive).write()
+    }
+
+    fn tag(&self, primitive: &Primitive) -> Element {
+        let point = |p: Point| {
+            let (x, y) = self.pixels(p);
+            (self.num(x), self.num(y))
+        };
+        match primitive {
+            Primitive::Polyline { points, pen } => {
+                let d: Vec<String> = points
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &p)| {
+                        let (x, y) = point(p);
+                        format!("{} {} {}", if i == 0 { "M" } else { "L" }, x, y)
+                    })
+                    .collect();
+                Element::new(
+                    "path",
+                    format!("d=\"{}\" {} fill=\"none\"", d.join(" "), stroke_attrs(pen)),
+                )
+            }
+            // SVG arcs can't be full circles, so they are split into pieces of at most 180 degrees
+            Primitive::Arc {
+                centre: (cx, cy),
+                radius,
+                start,
+                angle,
+                pen,
+            } => {
+                let pieces = (angle.abs() / 180.0).ceil().max(1.0) as usize;
+                let (x, y) = point(scene::point_at(*cx, *cy, *start, *radius));
+                let mut d = format!("M {} {}", x, y);
+                for i in 1..=pieces {
+                    let heading = start + angle * i as f32 / pieces as f32;
+                    let (x, y) = point(scene::point_at(*cx, *cy, heading, *radius));
+                    let sweep = if *angle > 0.0 { 1 } else { 0 };
+                    let r = self.num(*radius);
+                    d.push_str(&format!(" A {} {} 0 0 {} {} {}", r, r, sweep, x, y));
+                }
+                Element::new(
+                    "path",
+                    format!("d=\"{}\" {} fill=\"none\"", d, stroke_attrs(pen)),
+                )
+            }
+            Primitive::Circle {
+                centre,
+                radius,
+                pen,
+            } => {
+                let (cx, cy) = point(*centre);
+                Element::new(
+                    "circle",
+                    format!(
+                        "cx=\"{}\" cy=\"{}\" r=\"{}\" {} fill=\"none\"",
+                        cx,
+                        cy,
+                        self.num(*radius),
+                        stroke_attrs(pen)
+                    ),
+                )
+            }
+            Primitive::Ellipse {
+                centre,
+                crosswise,
+                along,
+                heading,
+                pen,
+            } => {
+                let (cx, cy) = point(*centre);
+                Element::new(
+                    "ellipse",
+                    format!(
+                        "cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" transform=\"rotate({} {} {})\" {} fill=\"none\"",
+                        cx,
+                        cy,
+                        self.num(*crosswise),
+                        self.num(*along),
+                        heading,
+                        cx,
+                        cy,
+                        stroke_attrs(pen)
+                    ),
+                )
+            }
+            Primitive::Fill { parts, color, rule } => {
+                let mut d = vec![];
+                for part in parts {
+                    for (i, &p) in part.iter().enumerate() {
+                        let (x, y) = point(p);
+                        d.push(format!("{} {} {}", if i == 0 { "M" } else { "L" }, x, y));
+                    }
+                    d.push("Z".to_string());
+                }
+                let rule = match rule {
+                    FillRule::NonZero => "nonzero",
+                    FillRule::EvenOdd => "evenodd",
+                };
+                Element::new(
+                    "path",
+                    format!(
+                        "d=\"{}\" fill=\"{}\" fill-rule=\"{}\" stroke=\"none\"",
+                        d.join(" "),
+                        color,
+                        rule
+                    ),
+                )
+            }
+            Primitive::Label {
+                position,
+                heading,
+                text,
+                font,
+                height,
+                color,
+                opacity,
+            } => {
+                let (x, y) = point(*position);
+                let mut attrs = format!(
+                    "x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\" fill=\"{}\"",
+                    x,
+                    y,
+                    escape_xml(font),
+                    height,
+                    color
+                );
+                if *opacity < 1.0 {
+                    attrs.push_str(&format!(" fill-opacity=\"{}\"", opacity));
+                }
+                if *heading != 0.0 {
+                    attrs.push_str(&format!(" transform=\"rotate({} {} {})\"", heading, x, y));
+                }
+                Element {
+                    name: "text",
+                    attrs,
+                    text: Some(escape_xml(text)),
+                }
+            }
+            Primitive::Turtle {
+                position,
+                heading,
+                shape,
+                size,
+                pen,
+                fill,
+            } => {
+                let (x, y) = self.pixels(*position);
+                let style = format!("{} fill=\"{}\"", stroke_attrs(pen), fill);
+                match shape {
+                    TurtleShape::Circle => Element::new(
+                        "circle",
+                        format!(
+                            "cx=\"{}\" cy=\"{}\" r=\"{}\" {}",
+                            self.num(x),
+                            self.num(y),
+                            size,
+                            style
+                        ),
+                    ),
+                    // pointing up, rotated to the turtle heading
+                    TurtleShape::Triangle => Element::new(
+                        "polygon",
+                        format!(
+                            "points=\"{},{} {},{} {},{}\" transform=\"rotate({} {} {})\" {}",
+                            self.num(x),
+                            self.num(y - size),
+                            self.num(x - size * 0.6),
+                            self.num(y + size * 0.6),
+                            self.num(x + size * 0.6),
+                            self.num(y + size * 0.6),
+                            heading,
+                            self.num(x),
+                            self.num(y),
+                            style
+                        ),
+                    ),
+                }
+            }
+        }
+    }
+
+    /* In an animated SVG the element appears when its turn comes. Lines drawn with a solid pen
+    are drawn along their length instead, by an animation inside the element */
+    fn reveal(&self, element: Element, drawn: bool, timing: Timing) -> String {
+        if self.animation.is_none() {
+            return element.write();
+        }
+        if drawn {
+            let animate = format!(
+                "<animate attributeName=\"stroke-dashoffset\" from=\"1\" to=\"0\" begin=\"{}ms\" dur=\"{}ms\" fill=\"freeze\" />",
+                timing.begin_ms, timing.dur_ms
+            );
+            element.write_with(
+                " pathLength=\"1\" stroke-dasharray=\"1\" stroke-dashoffset=\"1\"",
+                &animate,
+            )
+        } else {
+            format!(
+                "<g visibility=\"hidden\">{}{}</g>\n",
+                show_at(timing.begin_ms),
+                element.write().trim_end()
+            )
+        }
+    }
+
+    /* A primitive of the scene, in an animated SVG it is drawn or shown when its time comes */
+    fn timed_element(&self, primitive: &Primitive, timing: Timing) -> String {
+        match primitive {
+            // every line as its own element, for debugging
+            Primitive::Polyline { points, pen } if !self.options.merge_lines => {
+                let step = timing.dur_ms / (points.len() as u32 - 1);
+                let mut lines = String::new();
+                for (i, line) in points.windows(2).enumerate() {
+                    let (x1, y1) = self.pixels(line[0]);
+                    let (x2, y2) = self.pixels(line[1]);
+                    let element = Element::new(
+                        "line",
+                        format!(
+                            "x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" {}",
+                            self.num(x1),
+                            self.num(y1),
+                            self.num(x2),
+                            self.num(y2),
+                            stroke_attrs(pen)
+                        ),
+                    );
+                    let line_timing = Timing {
+                        begin_ms: timing.begin_ms + i as u32 * step,
+                        dur_ms: step,
+                    };
+                    lines.push_str(&self.reveal(element, pen.pattern.is_empty(), line_timing));
+                }
+                lines
+            }
+            primitive => {
+                // dashed lines already use the dash array, they just appear
+                let drawn = match primitive {
+                    Primitive::Polyline { pen, .. }
+                    | Primitive::Arc { pen, .. }
+                    | Primitive::Circle { pen, .. }
+                    | Primitive::Ellipse { pen, .. } => pen.pattern.is_empty(),
+                    _ => false,
+                };
+                self.reveal(self.tag(primitive), drawn, timing)
+            }
+        }
+    }
+
+    /* The drawing without the turtles */
+    pub fn drawing(&self, scene: &Scene) -> String {
+        self.drawing_where(scene, |_| true)
+    }
+
+    /* Only the primitives at the indexes kept, like the ones drawn by a frame of an animation */
+    fn drawing_where(&self, scene: &Scene, keep: impl Fn(usize) -> bool) -> String {
+        scene
+            .primitives()
+            .enumerate()
+            .filter(|&(i, _)| keep(i))
+            .map(|(i, primitive)| {
+                let timing = self
+                    .animation
+                    .and_then(|animation| animation.timings.get(i).copied())
+                    .unwrap_or_default();
+                self.timed_element(primitive, timing)
+            })
+            .collect()
+    }
+
+    /* The drawing with the turtles on top */
+    pub fn body(&self, scene: &Scene, turtles: &[Primitive]) -> String {
+        self.body_where(scene, |_| true, turtles)
+    }
+
+    /* The primitives at the indexes kept with the turtles on top, so a frame of an animation
+    can be made from the finished scene */
+    pub fn body_where(
+        &self,
+        scene: &Scene,
+        keep: impl Fn(usize) -> bool,
+        turtles: &[Primitive],
+    ) -> String {
+        let drawing = self.drawing_where(scene, keep);
+        let turtles: String = turtles.iter().map(|turtle| self.element(turtle)).collect();
+        match self.animation {
+            // the turtles are shown where they end up, once the drawing is finished
+            Some(animation) if !turtles.is_empty() => format!(
+                "{}<g visibility=\"hidden\">{}\n{}</g>\n",
+                drawing,
+                show_at(animation.time_ms),
+                turtles
+            ),
+            _ => format!("{}{}", drawing, turtles),
+        }
+    }
+
+    /* A whole SVG file around the body, the size of the canvas. When the image is fitted to the
+    drawing the view box is given instead: x, y, width and height in SVG pixels */
+    pub fn document(
+        &self,
+        scene: &Scene,
+        fitted: Option<(f32, f32, f32, f32)>,
+        body: &str,
+    ) -> String {
+        let (x, y, width, height) = fitted.unwrap_or((0.0, 0.0, self.width, self.height));
+        let mut svg = match fitted {
+            Some(_) => format!(
+                "<svg xmlns=\"{}\" width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\">",
+                NAMESPACE, width, height, x, y, width, height
+            ),
+            None => format!(
+                "<svg xmlns=\"{}\" width=\"{}\" height=\"{}\">",
+                NAMESPACE, width, height
+            ),
+        };
+        if let Some(background) = scene.background() {
+            svg.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" />\n",
+                x, y, width, height, background
+            ));
+        }
+        svg.push_str(body);
+        svg.push_str("</svg>");
+        svg
+    }
+}